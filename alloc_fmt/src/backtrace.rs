@@ -0,0 +1,171 @@
+//! Allocation-free stack traces.
+//!
+//! Frames are captured with the unwinder's `_Unwind_Backtrace` into a fixed-size buffer owned by
+//! the caller, and resolved with `dladdr`, neither of which allocates. Only dynamic symbols can be
+//! named that way; other frames are printed as an offset in their object file, which `addr2line`
+//! can resolve. Linking with `-rdynamic` exports the symbols of the executable too.
+//!
+//! The module is only built with the `print-backtrace` feature. It is what
+//! [`print_backtrace_and_abort`] prints, and it can be used directly by allocators that record
+//! where allocations come from.
+//!
+//! [`print_backtrace_and_abort`]: ../fn.print_backtrace_and_abort.html
+
+use core::ffi::{c_int, c_void, CStr};
+use core::fmt;
+
+/// Deepest stack captured.
+pub const MAX_FRAMES: usize = 32;
+
+const URC_NO_REASON: c_int = 0;
+const URC_END_OF_STACK: c_int = 5;
+
+type TraceFn = extern "C" fn(context: *mut c_void, arg: *mut c_void) -> c_int;
+
+extern "C" {
+    fn _Unwind_Backtrace(trace: TraceFn, arg: *mut c_void) -> c_int;
+    fn _Unwind_GetIP(context: *mut c_void) -> usize;
+}
+
+/// A captured stack, innermost frame first.
+#[derive(Clone, Copy)]
+pub struct Frames {
+    ips: [usize; MAX_FRAMES],
+    len: usize,
+}
+
+impl Frames {
+    /// A stack with no frames, to initialize buffers with.
+    pub const EMPTY: Frames = Frames {
+        ips: [0; MAX_FRAMES],
+        len: 0,
+    };
+
+    /// The instruction pointers of the frames, innermost first.
+    pub fn as_slice(&self) -> &[usize] {
+        &self.ips[..self.len]
+    }
+
+    /// FNV-1a over the instruction pointers.
+    pub fn hash(&self) -> u64 {
+        self.as_slice()
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325, |hash, &ip| {
+                (hash ^ ip as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+}
+
+struct Capture {
+    frames: Frames,
+    skip: usize,
+}
+
+extern "C" fn trace(context: *mut c_void, arg: *mut c_void) -> c_int {
+    let capture = unsafe { &mut *arg.cast::<Capture>() };
+    if capture.skip > 0 {
+        capture.skip -= 1;
+        return URC_NO_REASON;
+    }
+    let ip = unsafe { _Unwind_GetIP(context) };
+    if ip == 0 || capture.frames.len == MAX_FRAMES {
+        return URC_END_OF_STACK;
+    }
+    capture.frames.ips[capture.frames.len] = ip;
+    capture.frames.len += 1;
+    URC_NO_REASON
+}
+
+/// Captures the stack of the caller, leaving out `skip` more frames.
+#[inline(never)]
+pub fn capture(skip: usize) -> Frames {
+    let mut capture = Capture {
+        frames: Frames::EMPTY,
+        // Leave out this function too.
+        skip: skip + 1,
+    };
+    unsafe { _Unwind_Backtrace(trace, (&mut capture as *mut Capture).cast()) };
+    capture.frames
+}
+
+/// Where an instruction pointer lies, as far as `dladdr` knows.
+enum Location<'a> {
+    Symbol {
+        name: &'a str,
+        offset: usize,
+        file: &'a str,
+    },
+    File {
+        file: &'a str,
+        offset: usize,
+    },
+    Unknown,
+}
+
+fn locate<'a>(ip: usize) -> Location<'a> {
+    // Return addresses point after the call instruction.
+    let address = ip - 1;
+    let mut info: libc::Dl_info = unsafe { core::mem::zeroed() };
+    if unsafe { libc::dladdr(address as *const c_void, &mut info) } == 0 {
+        return Location::Unknown;
+    }
+    let file = name(info.dli_fname).unwrap_or("<unknown>");
+    match name(info.dli_sname) {
+        Some(name) => Location::Symbol {
+            name,
+            offset: address - info.dli_saddr as usize,
+            file,
+        },
+        None => Location::File {
+            file,
+            offset: address - info.dli_fbase as usize,
+        },
+    }
+}
+
+/// Displays a frame with its offset and object file.
+pub struct Frame(pub usize);
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match locate(self.0) {
+            Location::Symbol { name, offset, file } => write!(
+                f,
+                "{:#} + {:#x} ({})",
+                rustc_demangle::demangle(name),
+                offset,
+                file
+            ),
+            Location::File { file, offset } => write!(f, "{} + {:#x}", file, offset),
+            Location::Unknown => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Displays the function of a frame, or its address in its object file when
+/// the function is unknown.
+pub struct FunctionName(pub usize);
+
+impl fmt::Display for FunctionName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match locate(self.0) {
+            Location::Symbol { name, .. } => write!(f, "{:#}", rustc_demangle::demangle(name)),
+            Location::File { file, offset } => write!(f, "{}+{:#x}", file, offset),
+            Location::Unknown => write!(f, "{:#x}", self.0),
+        }
+    }
+}
+
+/// Prints one frame per line to stderr, with the given indentation.
+pub fn print(frames: &[usize], indent: &str) {
+    for (index, &ip) in frames.iter().enumerate() {
+        alloc_eprintln!("{}#{} {}", indent, index, Frame(ip));
+    }
+}
+
+fn name<'a>(ptr: *const libc::c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        return None;
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_str().ok()
+}
@@ -35,29 +35,53 @@
 //! sometimes detect recursion when there is none.
 //!
 //! Unlike the standard library assertion and panic macros, the stack is not unwound, and once an
-//! assertion failure or panic triggers, it cannot be caught or aborted.
+//! assertion failure or panic triggers, it cannot be caught or aborted. Tests exercising allocator
+//! assertions can opt out of this with [`set_catchable_panics`]: panics then go through the
+//! standard library's panic machinery, so that `#[should_panic]` and `catch_unwind` work, except on
+//! threads running allocator code (marked with [`enter_allocator`]), which must not unwind.
 //!
 //! [`alloc_println`]: macro.alloc_println.html
 //! [`alloc_debug_assert`]: macro.alloc_debug_assert.html
+//! [`set_catchable_panics`]: fn.set_catchable_panics.html
+//! [`enter_allocator`]: fn.enter_allocator.html
+//!
+//! # Logging
+//! The [`logger`] module provides leveled logging macros (`alloc_error!` to `alloc_trace!`) that
+//! are just as safe to use in an allocator, filtered at runtime through the `ALLOC_LOG` environment
+//! variable. With the `log` feature, the [`log_backend`] module lets allocator code use the `log`
+//! crate's macros as well.
+//!
+//! [`logger`]: logger/index.html
+//! [`log_backend`]: log_backend/index.html
+//!
+//! # Structured output
+//! The [`record`] module writes JSON objects and CSV rows, with the [`alloc_json`] and [`alloc_csv`]
+//! macros for flat records, so that allocators can export statistics without allocating.
+//!
+//! [`record`]: record/index.html
+//! [`alloc_json`]: macro.alloc_json.html
+//! [`alloc_csv`]: macro.alloc_csv.html
 
 #![no_std]
 #![feature(core_intrinsics)]
+#![feature(thread_local)]
 
-#[cfg(feature = "print-backtrace")]
-extern crate backtrace;
 extern crate libc;
+#[cfg(feature = "log")]
+extern crate log;
+#[cfg(feature = "print-backtrace")]
+extern crate rustc_demangle;
 extern crate spin;
 
+use core::cell::Cell;
 use core::fmt::{Arguments, Result as FmtResult, Write};
 use core::sync::atomic::AtomicBool;
-use core::sync::atomic::Ordering::SeqCst;
+use core::sync::atomic::Ordering::{Relaxed, SeqCst};
 
 // Import items that macros need to reference. They will reference them as $crate::foo instead of
 // core::foo since core isn't guaranteed to be imported in the user's scope.
 #[doc(hidden)]
 pub use core::fmt::write;
-#[doc(hidden)]
-pub use core::mem::drop;
 
 #[doc(hidden)]
 pub static STDERR_MTX: spin::Mutex<()> = spin::Mutex::new(());
@@ -75,24 +99,110 @@ pub struct FDWriter(pub libc::c_int);
 impl Write for FDWriter {
     #[inline]
     fn write_str(&mut self, s: &str) -> FmtResult {
-        let mut buf = s.as_bytes();
-        while !buf.is_empty() {
-            unsafe {
-                #[cfg(not(windows))]
-                let written = libc::write(self.0, buf.as_ptr() as *const _, buf.len());
-                #[cfg(windows)]
-                let written =
-                    libc::write(self.0, buf.as_ptr() as *const _, buf.len() as libc::c_uint);
-                if written < 1 {
-                    core::intrinsics::abort();
-                }
-                buf = &buf[written as usize..];
+        write_all(self.0, s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Writes all of `buf` to `fd`, aborting on failure.
+fn write_all(fd: libc::c_int, mut buf: &[u8]) {
+    while !buf.is_empty() {
+        unsafe {
+            #[cfg(not(windows))]
+            let written = libc::write(fd, buf.as_ptr() as *const _, buf.len());
+            #[cfg(windows)]
+            let written = libc::write(fd, buf.as_ptr() as *const _, buf.len() as libc::c_uint);
+            if written < 1 {
+                core::intrinsics::abort();
             }
+            buf = &buf[written as usize..];
+        }
+    }
+}
+
+/// Appends as much of `s` as fits to `buf[*len..]`, cutting it at a character boundary. Returns
+/// whether all of it fit.
+fn append(buf: &mut [u8], len: &mut usize, s: &str) -> bool {
+    let available = buf.len() - *len;
+    let mut n = s.len().min(available);
+    while !s.is_char_boundary(n) {
+        n -= 1;
+    }
+    buf[*len..*len + n].copy_from_slice(&s.as_bytes()[..n]);
+    *len += n;
+    n == s.len()
+}
+
+/// A [`Write`] implementation that formats into a buffer of `N` bytes on the stack.
+///
+/// Formatted output that does not fit is cut at a character boundary, [`is_truncated`] returns
+/// true, and writing returns an error from then on, so that formatting stops and callers such as
+/// the [`record`] writers report output that is incomplete.
+///
+/// The print macros format each message into a `FixedBufWriter` and write it with a single
+/// `write(2)`, so that messages from different threads do not interleave.
+///
+/// [`Write`]: https://doc.rust-lang.org/core/fmt/trait.Write.html
+/// [`is_truncated`]: #method.is_truncated
+/// [`record`]: record/index.html
+pub struct FixedBufWriter<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    truncated: bool,
+}
+
+impl<const N: usize> FixedBufWriter<N> {
+    pub const fn new() -> Self {
+        FixedBufWriter {
+            buf: [0; N],
+            len: 0,
+            truncated: false,
+        }
+    }
+
+    /// The text formatted so far.
+    pub fn as_str(&self) -> &str {
+        // Only whole strings or prefixes cut at character boundaries are ever appended.
+        unsafe { core::str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+
+    /// Whether some formatted output did not fit in the buffer.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Empties the buffer so that it can be reused.
+    pub fn clear(&mut self) {
+        self.len = 0;
+        self.truncated = false;
+    }
+
+    /// Writes the text formatted so far to the file descriptor `fd`, aborting the process if that
+    /// fails.
+    pub fn write_to_fd(&self, fd: libc::c_int) {
+        write_all(fd, &self.buf[..self.len]);
+    }
+}
+
+impl<const N: usize> Default for FixedBufWriter<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Write for FixedBufWriter<N> {
+    fn write_str(&mut self, s: &str) -> FmtResult {
+        if self.truncated || !append(&mut self.buf, &mut self.len, s) {
+            self.truncated = true;
+            return Err(core::fmt::Error);
         }
         Ok(())
     }
 }
 
+/// Size of the buffer the print macros format a message into. Longer messages are truncated.
+pub const PRINT_BUF_SIZE: usize = 1024;
+
 // We can't simply 'pub use core::intrinsics::abort' because its use requires
 // feature(core_intrinsics), which the user would have to enable.
 #[doc(hidden)]
@@ -100,26 +210,93 @@ pub unsafe fn abort() -> ! {
     core::intrinsics::abort();
 }
 
+#[doc(hidden)]
+pub fn print(fd: libc::c_int, mtx: &spin::Mutex<()>, fmt: Arguments) {
+    let mut buf = FixedBufWriter::<PRINT_BUF_SIZE>::new();
+    if write(&mut buf, fmt).is_err() && !buf.is_truncated() {
+        // A Display or Debug implementation returned an error.
+        unsafe { abort() };
+    }
+    let _guard = mtx.lock();
+    buf.write_to_fd(fd);
+    if buf.is_truncated() {
+        write_all(fd, b"... [truncated]\n");
+    }
+}
+
 #[doc(hidden)]
 #[macro_export]
 macro_rules! print_internal {
     ($file:expr, $mtx:expr, $fmt:expr) => {
-        // in case we're called from inside an unsafe block
-        #[allow(unused_unsafe)]
-        unsafe {
-            let guard = $mtx.lock();
+        $crate::print($file, &$mtx, $fmt)
+    };
+}
 
-            let mut fd = $crate::FDWriter($file);
-            let _ = $crate::write(&mut fd, $fmt).map_err(|_| {
-                $crate::abort();
-            });
+/// Formats into a caller-provided byte buffer and evaluates to the resulting `&str`.
+///
+/// The first argument is a `&mut [u8]` that the returned string borrows from; the rest are the
+/// same as for [`format`]. If the output does not fit, it is cut at a character boundary and ends
+/// with `...` to make the truncation visible.
+///
+/// ```
+/// # #[macro_use] extern crate alloc_fmt;
+/// # fn main() {
+/// let mut buf = [0u8; 16];
+/// assert_eq!(alloc_format_buf!(&mut buf, "{} + {}", 1, 2), "1 + 2");
+/// assert_eq!(alloc_format_buf!(&mut buf, "{:>20}", 1), "             ...");
+/// assert_eq!(alloc_format_buf!(&mut buf, "{}", "abcdefghijklmnopq"), "abcdefghijklm...");
+/// # }
+/// ```
+///
+/// [`format`]: https://doc.rust-lang.org/std/macro.format.html
+#[macro_export]
+macro_rules! alloc_format_buf {
+    ($buf:expr, $($arg:tt)*) => ($crate::format_buf($buf, format_args!($($arg)*)))
+}
 
-            // explicitly drop the guard so we're guaranteed it lives at least this long;
-            // since we don't actually use the guard, the compiler could theoretically
-            // drop it sooner.
-            $crate::drop(guard);
+#[doc(hidden)]
+pub fn format_buf<'a>(buf: &'a mut [u8], fmt: Arguments) -> &'a str {
+    struct SliceWriter<'a> {
+        buf: &'a mut [u8],
+        len: usize,
+        truncated: bool,
+    }
+
+    impl Write for SliceWriter<'_> {
+        fn write_str(&mut self, s: &str) -> FmtResult {
+            if !append(self.buf, &mut self.len, s) {
+                self.truncated = true;
+            }
+            Ok(())
         }
+    }
+
+    let mut writer = SliceWriter {
+        buf,
+        len: 0,
+        truncated: false,
     };
+    if write(&mut writer, fmt).is_err() {
+        unsafe { abort() };
+    }
+    let SliceWriter {
+        buf,
+        mut len,
+        truncated,
+    } = writer;
+    if truncated && buf.len() >= 3 {
+        len = len.min(buf.len() - 3);
+        while !is_char_boundary(buf, len) {
+            len -= 1;
+        }
+        append(buf, &mut len, "...");
+    }
+    unsafe { core::str::from_utf8_unchecked(&buf[..len]) }
+}
+
+fn is_char_boundary(buf: &[u8], index: usize) -> bool {
+    // Continuation bytes are 0b10xxxxxx.
+    index == 0 || index >= buf.len() || (buf[index] as i8) >= -0x40
 }
 
 #[macro_export]
@@ -146,13 +323,66 @@ macro_rules! alloc_eprintln {
     ($fmt:expr, $($arg:tt)*) => ($crate::alloc_eprint!(concat!($fmt, "\n"), $($arg)*));
 }
 
-// Sometimes backtraces allocate. If that allocation causes an assertion failure, then we can get
-// into an infinite recursion scenario. In order to detect this, we set this global bool to true,
-// and load it before printing a backtrace. If we find that it is true, we immediately abort
-// (essentially short-circuiting what would eventually happen if print_backtrace_and_abort
-// successfully finished executing).
-#[doc(hidden)]
-pub static IS_PANICKING: AtomicBool = AtomicBool::new(false);
+// Printing a backtrace can itself fail an assertion, for instance if it touches memory that the
+// allocator corrupted, and we can get into an infinite recursion scenario. In order to detect this,
+// we set this flag to true before printing a backtrace. If we find that it was already true, we
+// immediately abort (essentially short-circuiting what would eventually happen if
+// print_backtrace_and_abort successfully finished executing). It is per-thread so that a panic on
+// one thread does not cut short an unrelated panic on another.
+#[thread_local]
+static PANICKING: AtomicBool = AtomicBool::new(false);
+
+static CATCHABLE_PANICS: AtomicBool = AtomicBool::new(false);
+
+#[thread_local]
+static IN_ALLOCATOR: Cell<bool> = Cell::new(false);
+
+/// Marks the current thread as running allocator code until dropped. Returned by
+/// [`enter_allocator`].
+///
+/// [`enter_allocator`]: fn.enter_allocator.html
+pub struct AllocatorScope {
+    previous: bool,
+}
+
+impl Drop for AllocatorScope {
+    fn drop(&mut self) {
+        IN_ALLOCATOR.set(self.previous);
+    }
+}
+
+/// Marks the current thread as running allocator code until the returned guard is dropped. Scopes
+/// can be nested.
+///
+/// In allocator code, panics always abort, and messages logged through the `log` crate do not
+/// allocate.
+#[must_use]
+pub fn enter_allocator() -> AllocatorScope {
+    AllocatorScope {
+        previous: IN_ALLOCATOR.replace(true),
+    }
+}
+
+/// Whether the current thread is running allocator code.
+pub fn in_allocator() -> bool {
+    IN_ALLOCATOR.get()
+}
+
+/// Makes panics and assertion failures outside of allocator code unwind through `core::panic!`
+/// rather than abort, so that they can be caught. Off by default.
+///
+/// The message is still printed first, and panics in allocator code still abort, since unwinding
+/// out of a global allocator is undefined behavior.
+pub fn set_catchable_panics(catchable: bool) {
+    CATCHABLE_PANICS.store(catchable, Relaxed);
+}
+
+#[cfg(feature = "print-backtrace")]
+pub mod backtrace;
+#[cfg(feature = "log")]
+pub mod log_backend;
+pub mod logger;
+pub mod record;
 
 #[macro_export]
 macro_rules! alloc_panic {
@@ -170,12 +400,16 @@ macro_rules! alloc_panic {
 #[cold]
 pub fn panic(fmt_file_line_col: &(Arguments, &'static str, u32, u32)) -> ! {
     let (fmt, file, line, col) = *fmt_file_line_col;
-    alloc_eprint!("thread panicked at '");
-    print_internal!(STDERR, STDERR_MTX, fmt);
-    alloc_eprintln!("', {}:{}:{}", file, line, col);
+    // One write for the whole line, so that concurrent panics do not interleave.
+    alloc_eprintln!("thread panicked at '{}', {}:{}:{}", fmt, file, line, col);
+    if CATCHABLE_PANICS.load(Relaxed) && !in_allocator() {
+        core::panic!("{}", fmt);
+    }
     unsafe {
-        if IS_PANICKING.compare_and_swap(false, true, SeqCst) {
-            // compare_and_swap returns the old value; true means somebody's already panicking.
+        if PANICKING
+            .compare_exchange(false, true, SeqCst, SeqCst)
+            .is_err()
+        {
             alloc_eprintln!("thread panicked while panicking");
             core::intrinsics::abort();
         }
@@ -366,41 +600,23 @@ fn result_unwrap_failed<E: ::core::fmt::Debug>(msg: &str, err: E) -> ! {
 
 /// Print a backtrace and then abort the process.
 ///
-/// `print_backtrace_and_abort` should be called after any relevant output has been flushed to
-/// stderr so that even if this function crashes (since the `backtrace` crate does not guarantee
-/// allocation-free backtraces), as much information as possible has already been output.
+/// With the `print-backtrace` feature, the stack of the caller is printed through the
+/// [`backtrace`] module, which neither allocates nor takes locks other than the dynamic loader's.
+/// `print_backtrace_and_abort` should still be called after any relevant output has been flushed to
+/// stderr so that even if this function crashes, as much information as possible has already been
+/// output.
+///
+/// [`backtrace`]: backtrace/index.html
 #[doc(hidden)]
+#[inline(never)]
 pub unsafe fn print_backtrace_and_abort() -> ! {
-    // TODO(joshlf): Currently, this function prints itself and its callees in the trace. We should
-    // figure out a way to omit those and have the first printed frame be the caller's.
-
     #[cfg(feature = "print-backtrace")]
     {
-        backtrace::trace(|frame| {
-            let ip = frame.ip();
-            backtrace::resolve(ip, |symbol| {
-                if let Some(name) = symbol.name() {
-                    alloc_eprintln!("{}", name);
-                } else {
-                    alloc_eprintln!("<unknown function>");
-                }
-                if let Some(path) = symbol.filename() {
-                    if let Some(s) = path.to_str() {
-                        alloc_eprint!("\t{}", s);
-                    } else {
-                        alloc_eprint!("\t<unknown file>");
-                    }
-                } else {
-                    alloc_eprint!("\t<unknown file>");
-                }
-                if let Some(line) = symbol.lineno() {
-                    alloc_eprintln!(":{}", line);
-                } else {
-                    alloc_eprintln!();
-                }
-            });
-            true
-        });
+        // Leave out this function and `panic`, so that the first printed frame is the code that
+        // panicked.
+        let frames = backtrace::capture(2);
+        alloc_eprintln!("stack backtrace:");
+        backtrace::print(frames.as_slice(), "  ");
     }
     core::intrinsics::abort();
 }
@@ -413,6 +629,19 @@ fn never_called() {
     alloc_println!("foo");
     alloc_eprint!("foo");
     alloc_eprintln!("foo");
+    let mut buf = [0u8; 8];
+    let _: &str = alloc_format_buf!(&mut buf, "foo: {}", "bar");
+    let mut out = FixedBufWriter::<8>::new();
+    let _ = alloc_json!(&mut out);
+    let _ = alloc_json!(&mut out, "foo" => 1, "bar" => "baz",);
+    let _ = alloc_csv!(&mut out, "foo", 1);
+    alloc_log!(logger::Level::Info, "foo");
+    alloc_log!(target: "foo", logger::Level::Info, "foo: {}", "bar");
+    alloc_error!("foo");
+    alloc_warn!(target: "foo", "foo");
+    alloc_info!("foo: {}", "bar");
+    alloc_debug!("foo");
+    alloc_trace!(target: "foo", "foo: {}", "bar");
     alloc_assert!(false && true);
     alloc_assert!(false && true, "foo");
     alloc_assert!(false && true, "foo: {}", "bar");
@@ -0,0 +1,135 @@
+//! A backend for the [`log`] crate that allocator code can log through.
+//!
+//! Loggers usually allocate, so calling `log::debug!` from a global allocator recurses into it, or
+//! deadlocks on the logger's own locks. [`AllocLogger`] tells such calls apart with a thread-local
+//! flag, set while the current thread is in allocator code: those messages go through the
+//! allocation-free [`logger`] path, filtered by `ALLOC_LOG`, while every other message is passed to
+//! the application's logger as usual.
+//!
+//! Allocator code marks itself with [`enter_allocator`]. The flag is also set while the
+//! application's logger runs, so that the allocations it makes can log too.
+//!
+//! ```no_run
+//! # extern crate alloc_fmt;
+//! # extern crate log;
+//! use alloc_fmt::log_backend::{self, AllocLogger};
+//!
+//! struct AppLogger;
+//!
+//! impl log::Log for AppLogger {
+//!     fn enabled(&self, _: &log::Metadata) -> bool {
+//!         true
+//!     }
+//!     fn log(&self, record: &log::Record) {
+//!         // May allocate.
+//!     }
+//!     fn flush(&self) {}
+//! }
+//!
+//! static APP_LOGGER: AppLogger = AppLogger;
+//! static LOGGER: AllocLogger = AllocLogger::with_fallback(&APP_LOGGER);
+//!
+//! # fn main() {
+//! log_backend::init(&LOGGER).unwrap();
+//! {
+//!     let _allocator = log_backend::enter_allocator();
+//!     log::debug!("handled by alloc_fmt");
+//! }
+//! log::info!("handled by AppLogger");
+//! # }
+//! ```
+//!
+//! [`log`]: https://docs.rs/log
+//! [`AllocLogger`]: struct.AllocLogger.html
+//! [`logger`]: ../logger/index.html
+//! [`enter_allocator`]: fn.enter_allocator.html
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+use crate::logger::{self, Level};
+pub use crate::{enter_allocator, in_allocator, AllocatorScope};
+
+fn level(level: log::Level) -> Level {
+    match level {
+        log::Level::Error => Level::Error,
+        log::Level::Warn => Level::Warn,
+        log::Level::Info => Level::Info,
+        log::Level::Debug => Level::Debug,
+        log::Level::Trace => Level::Trace,
+    }
+}
+
+/// A [`log::Log`] implementation writing messages from allocator code without allocating, and
+/// passing the others to an optional fallback logger.
+///
+/// Without a fallback, all messages take the allocation-free path.
+///
+/// [`log::Log`]: https://docs.rs/log/0.4/log/trait.Log.html
+pub struct AllocLogger {
+    fallback: Option<&'static dyn Log>,
+}
+
+impl AllocLogger {
+    pub const fn new() -> Self {
+        AllocLogger { fallback: None }
+    }
+
+    /// Passes messages logged outside of allocator code to `fallback`.
+    pub const fn with_fallback(fallback: &'static dyn Log) -> Self {
+        AllocLogger {
+            fallback: Some(fallback),
+        }
+    }
+
+    /// The logger for the current message, if not the allocation-free path.
+    fn fallback(&self) -> Option<&'static dyn Log> {
+        self.fallback.filter(|_| !in_allocator())
+    }
+}
+
+impl Default for AllocLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Log for AllocLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        match self.fallback() {
+            Some(fallback) => {
+                let _scope = enter_allocator();
+                fallback.enabled(metadata)
+            }
+            None => logger::enabled(level(metadata.level()), metadata.target()),
+        }
+    }
+
+    fn log(&self, record: &Record) {
+        match self.fallback() {
+            Some(fallback) => {
+                let _scope = enter_allocator();
+                fallback.log(record)
+            }
+            None => {
+                let level = level(record.level());
+                if logger::enabled(level, record.target()) {
+                    logger::log(level, record.target(), *record.args());
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(fallback) = self.fallback() {
+            let _scope = enter_allocator();
+            fallback.flush();
+        }
+    }
+}
+
+/// Installs `logger` as the `log` crate's logger, letting every level through to it.
+pub fn init(logger: &'static AllocLogger) -> Result<(), SetLoggerError> {
+    log::set_logger(logger)?;
+    log::set_max_level(LevelFilter::Trace);
+    Ok(())
+}
@@ -0,0 +1,387 @@
+//! Allocation-safe leveled logging.
+//!
+//! The `alloc_error!`, `alloc_warn!`, `alloc_info!`, `alloc_debug!` and `alloc_trace!` macros log a
+//! message at their level, with the calling module as target. Messages that pass the filter are
+//! formatted into a stack buffer and written to stderr with a single `write(2)`, like the print
+//! macros, so they can be used in a global allocator.
+//!
+//! The filter is read from the `ALLOC_LOG` environment variable the first time something is logged.
+//! It is a comma-separated list of directives, each of which is either a level (`off`, `error`,
+//! `warn`, `info`, `debug` or `trace`) setting the default, `target=level` setting the level of a
+//! module and its submodules, or a bare target enabling everything from it. The most specific
+//! target wins. Without `ALLOC_LOG`, messages up to `info` are shown:
+//!
+//! ```text
+//! ALLOC_LOG=warn,memory_allocator_performance_rs::global_alloc=trace
+//! ```
+//!
+//! Messages go to the file descriptor in `ALLOC_LOG_FD`, if set, or to the one given to
+//! [`set_log_fd`].
+//!
+//! [`set_log_fd`]: fn.set_log_fd.html
+
+use core::ffi::{c_int, CStr};
+use core::fmt::{self, Arguments};
+use core::sync::atomic::{AtomicI32, Ordering::Relaxed};
+
+/// Directives kept from `ALLOC_LOG`; the ones after are ignored.
+const MAX_DIRECTIVES: usize = 16;
+/// Bytes kept from `ALLOC_LOG`; the rest is ignored.
+const MAX_SPEC_LEN: usize = 256;
+const OFF: u8 = 0;
+const DEFAULT_LEVEL: u8 = Level::Info as u8;
+
+/// The importance of a message, from the most to the least important.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// Parses a level name, `off` included, ignoring case.
+fn parse_level(name: &[u8]) -> Option<u8> {
+    let levels = [
+        (&b"off"[..], OFF),
+        (b"error", Level::Error as u8),
+        (b"warn", Level::Warn as u8),
+        (b"info", Level::Info as u8),
+        (b"debug", Level::Debug as u8),
+        (b"trace", Level::Trace as u8),
+    ];
+    levels
+        .iter()
+        .find(|(level, _)| level.eq_ignore_ascii_case(name))
+        .map(|&(_, level)| level)
+}
+
+/// A target prefix, as a range of `Filter::spec`, and its maximum level.
+#[derive(Clone, Copy)]
+struct Directive {
+    start: usize,
+    end: usize,
+    level: u8,
+}
+
+struct Filter {
+    /// A copy of `ALLOC_LOG`, which directive targets point into.
+    spec: [u8; MAX_SPEC_LEN],
+    directives: [Directive; MAX_DIRECTIVES],
+    len: usize,
+    default: u8,
+    /// The highest level of any target, to reject most messages without looking at targets.
+    max: u8,
+    /// From `ALLOC_LOG_FD`.
+    fd: Option<c_int>,
+}
+
+impl Filter {
+    fn from_env() -> Filter {
+        let mut filter = Filter::new(getenv(c"ALLOC_LOG"));
+        filter.fd = getenv(c"ALLOC_LOG_FD").and_then(parse_fd);
+        filter
+    }
+
+    fn new(spec: Option<&[u8]>) -> Filter {
+        let mut filter = Filter {
+            spec: [0; MAX_SPEC_LEN],
+            directives: [Directive {
+                start: 0,
+                end: 0,
+                level: OFF,
+            }; MAX_DIRECTIVES],
+            len: 0,
+            default: DEFAULT_LEVEL,
+            max: DEFAULT_LEVEL,
+            fd: None,
+        };
+        if let Some(spec) = spec {
+            filter.parse(spec);
+        }
+        filter.max = filter.directives[..filter.len]
+            .iter()
+            .map(|directive| directive.level)
+            .fold(filter.default, u8::max);
+        filter
+    }
+
+    fn parse(&mut self, spec: &[u8]) {
+        let len = spec.len().min(MAX_SPEC_LEN);
+        self.spec[..len].copy_from_slice(&spec[..len]);
+        let mut start = 0;
+        while start < len {
+            let end = self.spec[start..len]
+                .iter()
+                .position(|&b| b == b',')
+                .map_or(len, |comma| start + comma);
+            self.parse_directive(start, end);
+            start = end + 1;
+        }
+    }
+
+    /// Parses the directive in `spec[start..end]`, ignoring it if invalid.
+    fn parse_directive(&mut self, start: usize, end: usize) {
+        let (start, end) = trim(&self.spec, start, end);
+        if start == end {
+            return;
+        }
+        let part = &self.spec[start..end];
+        let (target_end, level) = match part.iter().position(|&b| b == b'=') {
+            Some(eq) => match parse_level(part[eq + 1..].trim_ascii()) {
+                Some(level) => (trim(&self.spec, start, start + eq).1, level),
+                None => return,
+            },
+            None => match parse_level(part) {
+                Some(level) => {
+                    self.default = level;
+                    return;
+                }
+                None => (end, Level::Trace as u8),
+            },
+        };
+        if target_end == start {
+            return;
+        }
+        if self.len < MAX_DIRECTIVES && core::str::from_utf8(&self.spec[start..target_end]).is_ok()
+        {
+            self.directives[self.len] = Directive {
+                start,
+                end: target_end,
+                level,
+            };
+            self.len += 1;
+        }
+    }
+
+    fn level_for(&self, target: &str) -> u8 {
+        let mut best: Option<&Directive> = None;
+        for directive in &self.directives[..self.len] {
+            let prefix = &self.spec[directive.start..directive.end];
+            let matches = target.as_bytes().starts_with(prefix)
+                && (target.len() == prefix.len() || target[prefix.len()..].starts_with("::"));
+            let longer = best.is_none_or(|best| best.end - best.start <= prefix.len());
+            if matches && longer {
+                best = Some(directive);
+            }
+        }
+        best.map_or(self.default, |directive| directive.level)
+    }
+}
+
+fn trim(spec: &[u8], mut start: usize, mut end: usize) -> (usize, usize) {
+    while start < end && spec[start].is_ascii_whitespace() {
+        start += 1;
+    }
+    while end > start && spec[end - 1].is_ascii_whitespace() {
+        end -= 1;
+    }
+    (start, end)
+}
+
+fn getenv(name: &CStr) -> Option<&'static [u8]> {
+    let value = unsafe { libc::getenv(name.as_ptr()) };
+    if value.is_null() {
+        return None;
+    }
+    Some(unsafe { CStr::from_ptr(value) }.to_bytes())
+}
+
+fn parse_fd(value: &[u8]) -> Option<c_int> {
+    let value = core::str::from_utf8(value).ok()?;
+    value.trim().parse().ok().filter(|&fd: &c_int| fd >= 0)
+}
+
+static FILTER: spin::Once<Filter> = spin::Once::new();
+/// Set by `set_log_fd`, -1 until then.
+static LOG_FD: AtomicI32 = AtomicI32::new(-1);
+
+fn filter() -> &'static Filter {
+    FILTER.call_once(Filter::from_env)
+}
+
+/// Sends log messages to the file descriptor `fd` instead of stderr. `ALLOC_LOG_FD` takes
+/// precedence.
+pub fn set_log_fd(fd: c_int) {
+    LOG_FD.store(fd, Relaxed);
+}
+
+fn log_fd() -> c_int {
+    let fd = LOG_FD.load(Relaxed);
+    filter()
+        .fd
+        .or((fd >= 0).then_some(fd))
+        .unwrap_or(crate::STDERR)
+}
+
+/// Whether a message at `level` from `target` would be logged.
+pub fn enabled(level: Level, target: &str) -> bool {
+    let filter = filter();
+    level as u8 <= filter.max && level as u8 <= filter.level_for(target)
+}
+
+#[doc(hidden)]
+pub fn log(level: Level, target: &str, args: Arguments) {
+    crate::print(
+        log_fd(),
+        &crate::STDERR_MTX,
+        format_args!("[{:<5} {}] {}\n", level, target, args),
+    );
+}
+
+/// Logs a message at the given level, with the calling module or `target:` as target.
+///
+/// ```
+/// # #[macro_use] extern crate alloc_fmt;
+/// # use alloc_fmt::logger::Level;
+/// # fn main() {
+/// alloc_log!(Level::Info, "{} bytes mapped", 4096);
+/// alloc_log!(target: "arena", Level::Debug, "resetting");
+/// # }
+/// ```
+#[macro_export]
+macro_rules! alloc_log {
+    (target: $target:expr, $level:expr, $($arg:tt)+) => ({
+        let level = $level;
+        let target = $target;
+        if $crate::logger::enabled(level, target) {
+            $crate::logger::log(level, target, format_args!($($arg)+));
+        }
+    });
+    ($level:expr, $($arg:tt)+) => ($crate::alloc_log!(target: module_path!(), $level, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! alloc_error {
+    (target: $target:expr, $($arg:tt)+) => ($crate::alloc_log!(target: $target, $crate::logger::Level::Error, $($arg)+));
+    ($($arg:tt)+) => ($crate::alloc_log!($crate::logger::Level::Error, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! alloc_warn {
+    (target: $target:expr, $($arg:tt)+) => ($crate::alloc_log!(target: $target, $crate::logger::Level::Warn, $($arg)+));
+    ($($arg:tt)+) => ($crate::alloc_log!($crate::logger::Level::Warn, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! alloc_info {
+    (target: $target:expr, $($arg:tt)+) => ($crate::alloc_log!(target: $target, $crate::logger::Level::Info, $($arg)+));
+    ($($arg:tt)+) => ($crate::alloc_log!($crate::logger::Level::Info, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! alloc_debug {
+    (target: $target:expr, $($arg:tt)+) => ($crate::alloc_log!(target: $target, $crate::logger::Level::Debug, $($arg)+));
+    ($($arg:tt)+) => ($crate::alloc_log!($crate::logger::Level::Debug, $($arg)+));
+}
+
+#[macro_export]
+macro_rules! alloc_trace {
+    (target: $target:expr, $($arg:tt)+) => ($crate::alloc_log!(target: $target, $crate::logger::Level::Trace, $($arg)+));
+    ($($arg:tt)+) => ($crate::alloc_log!($crate::logger::Level::Trace, $($arg)+));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ERROR: u8 = Level::Error as u8;
+    const WARN: u8 = Level::Warn as u8;
+    const INFO: u8 = Level::Info as u8;
+    const DEBUG: u8 = Level::Debug as u8;
+    const TRACE: u8 = Level::Trace as u8;
+
+    fn filter(spec: &str) -> Filter {
+        Filter::new(Some(spec.as_bytes()))
+    }
+
+    #[test]
+    fn default_level() {
+        assert_eq!(Filter::new(None).level_for("any"), INFO);
+        assert_eq!(filter("warn").level_for("any"), WARN);
+        assert_eq!(filter("TRACE").level_for("any"), TRACE);
+        let off = filter("off");
+        assert_eq!(off.level_for("any"), OFF);
+        assert_eq!(off.max, OFF);
+    }
+
+    #[test]
+    fn per_target() {
+        let filter = filter("warn,arena=debug,sbrk");
+        assert_eq!(filter.level_for("arena"), DEBUG);
+        assert_eq!(filter.level_for("arena::region"), DEBUG);
+        assert_eq!(filter.level_for("arenas"), WARN);
+        assert_eq!(filter.level_for("sbrk"), TRACE);
+        assert_eq!(filter.level_for("other"), WARN);
+        assert_eq!(filter.max, TRACE);
+    }
+
+    #[test]
+    fn longest_prefix_wins() {
+        for spec in [
+            "a=error,a::b=trace,a::b::c=warn",
+            "a::b::c=warn,a::b=trace,a=error",
+        ] {
+            let filter = filter(spec);
+            assert_eq!(filter.level_for("a"), ERROR, "{}", spec);
+            assert_eq!(filter.level_for("a::x"), ERROR, "{}", spec);
+            assert_eq!(filter.level_for("a::b"), TRACE, "{}", spec);
+            assert_eq!(filter.level_for("a::b::c::d"), WARN, "{}", spec);
+        }
+        // Among equal targets, the last one wins.
+        assert_eq!(filter("a=error,a=debug").level_for("a"), DEBUG);
+    }
+
+    #[test]
+    fn bad_levels_are_ignored() {
+        let filter = filter("arena=loud,sbrk=debug,=warn");
+        assert_eq!(filter.len, 1);
+        assert_eq!(filter.level_for("arena"), INFO);
+        assert_eq!(filter.level_for("sbrk"), DEBUG);
+        assert_eq!(filter.default, INFO);
+    }
+
+    #[test]
+    fn empty_segments_and_spaces() {
+        let filter = filter(",, warn ,,arena = debug ,");
+        assert_eq!(filter.len, 1);
+        assert_eq!(filter.default, WARN);
+        assert_eq!(filter.level_for("arena"), DEBUG);
+        assert_eq!(Filter::new(Some(b"")).level_for("any"), INFO);
+    }
+
+    #[test]
+    fn extra_directives_are_dropped() {
+        let filter = filter("t0,t1,t2,t3,t4,t5,t6,t7,t8,t9,t10,t11,t12,t13,t14,t15,t16");
+        assert_eq!(filter.len, MAX_DIRECTIVES);
+        assert_eq!(filter.level_for("t15"), TRACE);
+        assert_eq!(filter.level_for("t16"), INFO);
+    }
+
+    #[test]
+    fn fd() {
+        assert_eq!(parse_fd(b" 3 "), Some(3));
+        assert_eq!(parse_fd(b"-1"), None);
+        assert_eq!(parse_fd(b"stderr"), None);
+    }
+}
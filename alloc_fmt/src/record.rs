@@ -0,0 +1,589 @@
+//! Structured output that is safe to produce from a global allocator: JSON objects and CSV rows.
+//!
+//! [`JsonObject`], [`JsonArray`] and [`CsvRow`] write a record piece by piece to any [`Write`]
+//! implementation, escaping strings as they go, without allocating. The [`alloc_json!`] and
+//! [`alloc_csv!`] macros cover the common case of a flat record. Records are usually written to a
+//! [`FixedBufWriter`] and then to a file descriptor in one go, in which case writing them fails if
+//! they do not fit, or, when their size is not bounded, to a [`BufferedFdWriter`], which can be
+//! used from an `atexit` hook or a signal handler.
+//!
+//! ```
+//! # #[macro_use] extern crate alloc_fmt;
+//! # use alloc_fmt::FixedBufWriter;
+//! # use alloc_fmt::record::JsonObject;
+//! # fn main() {
+//! let mut out = FixedBufWriter::<256>::new();
+//! let mut stats = JsonObject::new(&mut out);
+//! stats.field("allocator", "arena \"main\"").field("live_bytes", 4096);
+//! stats.array("sizes", |sizes| {
+//!     sizes.value(16).value(32);
+//! });
+//! stats.finish().unwrap();
+//! assert_eq!(
+//!     out.as_str(),
+//!     r#"{"allocator":"arena \"main\"","live_bytes":4096,"sizes":[16,32]}"#
+//! );
+//!
+//! out.clear();
+//! alloc_csv!(&mut out, "arena, main", 4096).unwrap();
+//! assert_eq!(out.as_str(), "\"arena, main\",4096\n");
+//! # }
+//! ```
+//!
+//! [`JsonObject`]: struct.JsonObject.html
+//! [`JsonArray`]: struct.JsonArray.html
+//! [`CsvRow`]: struct.CsvRow.html
+//! [`Write`]: https://doc.rust-lang.org/core/fmt/trait.Write.html
+//! [`alloc_json!`]: ../macro.alloc_json.html
+//! [`alloc_csv!`]: ../macro.alloc_csv.html
+//! [`FixedBufWriter`]: ../struct.FixedBufWriter.html
+//! [`BufferedFdWriter`]: struct.BufferedFdWriter.html
+
+use core::fmt::{self, Display, Write};
+
+/// A value that can be written as JSON.
+pub trait JsonValue {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result;
+}
+
+impl<T: JsonValue + ?Sized> JsonValue for &T {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        (**self).write_json(out)
+    }
+}
+
+impl JsonValue for bool {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        out.write_str(if *self { "true" } else { "false" })
+    }
+}
+
+macro_rules! json_integers {
+    ($($ty:ty)*) => {
+        $(
+            impl JsonValue for $ty {
+                fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+                    write!(out, "{}", self)
+                }
+            }
+        )*
+    };
+}
+
+json_integers!(u8 u16 u32 u64 u128 usize i8 i16 i32 i64 i128 isize);
+
+macro_rules! json_floats {
+    ($($ty:ty)*) => {
+        $(
+            /// Infinities and NaN, which JSON cannot represent, are written as `null`.
+            impl JsonValue for $ty {
+                fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+                    if self.is_finite() {
+                        write!(out, "{}", self)
+                    } else {
+                        out.write_str("null")
+                    }
+                }
+            }
+        )*
+    };
+}
+
+json_floats!(f32 f64);
+
+impl JsonValue for str {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        JsonString(self).write_json(out)
+    }
+}
+
+impl<T: JsonValue> JsonValue for Option<T> {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        match self {
+            Some(value) => value.write_json(out),
+            None => out.write_str("null"),
+        }
+    }
+}
+
+impl<T: JsonValue> JsonValue for [T] {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        let mut array = JsonArray::new(out);
+        for value in self {
+            array.value(value);
+        }
+        array.finish()
+    }
+}
+
+impl<T: JsonValue, const N: usize> JsonValue for [T; N] {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        self[..].write_json(out)
+    }
+}
+
+/// Writes the `Display` output of a value as a JSON string.
+pub struct JsonString<T: Display>(pub T);
+
+impl<T: Display> JsonValue for JsonString<T> {
+    fn write_json(&self, out: &mut dyn Write) -> fmt::Result {
+        out.write_char('"')?;
+        write!(JsonEscaper(&mut *out), "{}", self.0)?;
+        out.write_char('"')
+    }
+}
+
+/// Escapes the text written to it for the inside of a JSON string.
+struct JsonEscaper<'a>(&'a mut dyn Write);
+
+impl Write for JsonEscaper<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut start = 0;
+        for (i, c) in s.char_indices() {
+            let escape = match c {
+                '"' => "\\\"",
+                '\\' => "\\\\",
+                '\n' => "\\n",
+                '\r' => "\\r",
+                '\t' => "\\t",
+                c if (c as u32) < 0x20 => "",
+                _ => continue,
+            };
+            self.0.write_str(&s[start..i])?;
+            if escape.is_empty() {
+                write!(self.0, "\\u{:04x}", c as u32)?;
+            } else {
+                self.0.write_str(escape)?;
+            }
+            start = i + c.len_utf8();
+        }
+        self.0.write_str(&s[start..])
+    }
+}
+
+/// A JSON object being written, field by field.
+///
+/// The first error returned by the underlying writer stops all further output and is returned by
+/// [`finish`], which closes the object.
+///
+/// [`finish`]: #method.finish
+pub struct JsonObject<'a> {
+    out: &'a mut dyn Write,
+    empty: bool,
+    result: fmt::Result,
+}
+
+impl<'a> JsonObject<'a> {
+    /// Starts an object.
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        let result = out.write_char('{');
+        JsonObject {
+            out,
+            empty: true,
+            result,
+        }
+    }
+
+    /// Writes the separator and name of the next field.
+    fn name(&mut self, name: &str) -> bool {
+        if self.result.is_ok() {
+            let separator = if self.empty { "" } else { "," };
+            self.empty = false;
+            self.result = self
+                .out
+                .write_str(separator)
+                .and_then(|()| name.write_json(self.out))
+                .and_then(|()| self.out.write_char(':'));
+        }
+        self.result.is_ok()
+    }
+
+    pub fn field(&mut self, name: &str, value: impl JsonValue) -> &mut Self {
+        if self.name(name) {
+            self.result = value.write_json(self.out);
+        }
+        self
+    }
+
+    /// Adds a nested object, whose fields `body` writes.
+    pub fn object(&mut self, name: &str, body: impl FnOnce(&mut JsonObject)) -> &mut Self {
+        if self.name(name) {
+            let mut object = JsonObject::new(&mut *self.out);
+            body(&mut object);
+            self.result = object.finish();
+        }
+        self
+    }
+
+    /// Adds an array, whose elements `body` writes.
+    pub fn array(&mut self, name: &str, body: impl FnOnce(&mut JsonArray)) -> &mut Self {
+        if self.name(name) {
+            let mut array = JsonArray::new(&mut *self.out);
+            body(&mut array);
+            self.result = array.finish();
+        }
+        self
+    }
+
+    /// Closes the object.
+    pub fn finish(&mut self) -> fmt::Result {
+        self.result?;
+        self.out.write_char('}')
+    }
+}
+
+/// A JSON array being written, element by element. Errors are handled as in [`JsonObject`].
+///
+/// [`JsonObject`]: struct.JsonObject.html
+pub struct JsonArray<'a> {
+    out: &'a mut dyn Write,
+    empty: bool,
+    result: fmt::Result,
+}
+
+impl<'a> JsonArray<'a> {
+    /// Starts an array.
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        let result = out.write_char('[');
+        JsonArray {
+            out,
+            empty: true,
+            result,
+        }
+    }
+
+    /// Writes the separator before the next element.
+    fn separator(&mut self) -> bool {
+        if self.result.is_ok() && !self.empty {
+            self.result = self.out.write_char(',');
+        }
+        self.empty = false;
+        self.result.is_ok()
+    }
+
+    pub fn value(&mut self, value: impl JsonValue) -> &mut Self {
+        if self.separator() {
+            self.result = value.write_json(self.out);
+        }
+        self
+    }
+
+    /// Adds an object, whose fields `body` writes.
+    pub fn object(&mut self, body: impl FnOnce(&mut JsonObject)) -> &mut Self {
+        if self.separator() {
+            let mut object = JsonObject::new(&mut *self.out);
+            body(&mut object);
+            self.result = object.finish();
+        }
+        self
+    }
+
+    /// Adds a nested array, whose elements `body` writes.
+    pub fn array(&mut self, body: impl FnOnce(&mut JsonArray)) -> &mut Self {
+        if self.separator() {
+            let mut array = JsonArray::new(&mut *self.out);
+            body(&mut array);
+            self.result = array.finish();
+        }
+        self
+    }
+
+    /// Closes the array.
+    pub fn finish(&mut self) -> fmt::Result {
+        self.result?;
+        self.out.write_char(']')
+    }
+}
+
+/// A CSV row being written, field by field, following RFC 4180: fields containing a comma, a quote
+/// or a line break are quoted, with quotes doubled. Errors are handled as in [`JsonObject`].
+///
+/// [`JsonObject`]: struct.JsonObject.html
+pub struct CsvRow<'a> {
+    out: &'a mut dyn Write,
+    empty: bool,
+    result: fmt::Result,
+}
+
+impl<'a> CsvRow<'a> {
+    pub fn new(out: &'a mut dyn Write) -> Self {
+        CsvRow {
+            out,
+            empty: true,
+            result: Ok(()),
+        }
+    }
+
+    /// Adds a field holding the formatted `value`.
+    pub fn field(&mut self, value: impl Display) -> &mut Self {
+        if self.result.is_err() {
+            return self;
+        }
+        let separator = if self.empty { "" } else { "," };
+        self.empty = false;
+        // Format once to learn whether the field needs quoting, then for real.
+        let mut scan = NeedsQuotes(false);
+        let _ = write!(scan, "{}", value);
+        self.result = self.out.write_str(separator).and_then(|()| {
+            if scan.0 {
+                self.out.write_char('"')?;
+                write!(CsvEscaper(&mut *self.out), "{}", value)?;
+                self.out.write_char('"')
+            } else {
+                write!(self.out, "{}", value)
+            }
+        });
+        self
+    }
+
+    /// Ends the row with a line break.
+    pub fn finish(&mut self) -> fmt::Result {
+        self.result?;
+        self.out.write_char('\n')
+    }
+}
+
+/// Records whether the text written to it needs quoting in a CSV field.
+struct NeedsQuotes(bool);
+
+impl Write for NeedsQuotes {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0 |= s.contains([',', '"', '\n', '\r']);
+        Ok(())
+    }
+}
+
+/// Doubles the quotes in the text written to it.
+struct CsvEscaper<'a>(&'a mut dyn Write);
+
+impl Write for CsvEscaper<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, part) in s.split('"').enumerate() {
+            if i > 0 {
+                self.0.write_str("\"\"")?;
+            }
+            self.0.write_str(part)?;
+        }
+        Ok(())
+    }
+}
+
+/// A [`Write`] implementation that buffers `N` bytes on the stack and writes them to a file
+/// descriptor whenever the buffer fills up, and when flushed or dropped.
+///
+/// Unlike [`FixedBufWriter`], output of any length can be written, but in several `write(2)`
+/// calls. Like the print macros, it aborts the process if writing fails.
+///
+/// [`Write`]: https://doc.rust-lang.org/core/fmt/trait.Write.html
+/// [`FixedBufWriter`]: ../struct.FixedBufWriter.html
+pub struct BufferedFdWriter<const N: usize> {
+    fd: libc::c_int,
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> BufferedFdWriter<N> {
+    pub const fn new(fd: libc::c_int) -> Self {
+        BufferedFdWriter {
+            fd,
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    /// Writes out the buffered bytes.
+    pub fn flush(&mut self) {
+        crate::write_all(self.fd, &self.buf[..self.len]);
+        self.len = 0;
+    }
+}
+
+impl<const N: usize> Write for BufferedFdWriter<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut bytes = s.as_bytes();
+        while !bytes.is_empty() {
+            if self.len == N {
+                self.flush();
+            }
+            let n = bytes.len().min(N - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+        Ok(())
+    }
+}
+
+impl<const N: usize> Drop for BufferedFdWriter<N> {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// Writes a flat JSON object to a [`Write`] implementation and evaluates to a `fmt::Result`.
+///
+/// Fields are given as `name => value`, where values implement [`JsonValue`]. No line break is
+/// written after the object.
+///
+/// ```
+/// # #[macro_use] extern crate alloc_fmt;
+/// # use alloc_fmt::FixedBufWriter;
+/// # fn main() {
+/// let mut out = FixedBufWriter::<64>::new();
+/// alloc_json!(&mut out, "allocs" => 3, "peak" => None::<u64>, "ratio" => 0.5).unwrap();
+/// assert_eq!(out.as_str(), r#"{"allocs":3,"peak":null,"ratio":0.5}"#);
+/// # }
+/// ```
+///
+/// [`Write`]: https://doc.rust-lang.org/core/fmt/trait.Write.html
+/// [`JsonValue`]: record/trait.JsonValue.html
+#[macro_export]
+macro_rules! alloc_json {
+    ($out:expr $(, $name:expr => $value:expr)* $(,)?) => ({
+        let mut object = $crate::record::JsonObject::new($out);
+        $(object.field($name, $value);)*
+        object.finish()
+    });
+}
+
+/// Writes a CSV row, line break included, to a [`Write`] implementation and evaluates to a
+/// `fmt::Result`.
+///
+/// Each field is formatted with `Display` and quoted if needed.
+///
+/// ```
+/// # #[macro_use] extern crate alloc_fmt;
+/// # use alloc_fmt::FixedBufWriter;
+/// # fn main() {
+/// let mut out = FixedBufWriter::<64>::new();
+/// alloc_csv!(&mut out, "size", "count").unwrap();
+/// alloc_csv!(&mut out, 16, "say \"hi\"").unwrap();
+/// assert_eq!(out.as_str(), "size,count\n16,\"say \"\"hi\"\"\"\n");
+/// # }
+/// ```
+///
+/// [`Write`]: https://doc.rust-lang.org/core/fmt/trait.Write.html
+#[macro_export]
+macro_rules! alloc_csv {
+    ($out:expr $(, $value:expr)* $(,)?) => ({
+        let mut row = $crate::record::CsvRow::new($out);
+        $(row.field($value);)*
+        row.finish()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FixedBufWriter;
+
+    fn json(value: impl JsonValue) -> FixedBufWriter<128> {
+        let mut out = FixedBufWriter::new();
+        value.write_json(&mut out).unwrap();
+        out
+    }
+
+    fn csv(fields: &[&str]) -> FixedBufWriter<128> {
+        let mut out = FixedBufWriter::new();
+        let mut row = CsvRow::new(&mut out);
+        for field in fields {
+            row.field(field);
+        }
+        row.finish().unwrap();
+        out
+    }
+
+    #[test]
+    fn json_escapes_quotes_and_backslashes() {
+        assert_eq!(json(r#"say "hi" \o/"#).as_str(), r#""say \"hi\" \\o/""#);
+    }
+
+    #[test]
+    fn json_escapes_control_characters() {
+        assert_eq!(json("a\nb\rc\td").as_str(), r#""a\nb\rc\td""#);
+        assert_eq!(
+            json("\u{0}\u{1}\u{1b}\u{1f}").as_str(),
+            r#""\u0000\u0001\u001b\u001f""#
+        );
+        // Only control characters below 0x20 have to be escaped.
+        assert_eq!(json("\u{7f} é ✓").as_str(), "\"\u{7f} é ✓\"");
+    }
+
+    #[test]
+    fn json_string_escapes_display_output() {
+        assert_eq!(
+            json(JsonString(format_args!("{:?}", "\n"))).as_str(),
+            r#""\"\\n\"""#
+        );
+    }
+
+    #[test]
+    fn json_values() {
+        assert_eq!(json(f64::NAN).as_str(), "null");
+        assert_eq!(json(-0.5f32).as_str(), "-0.5");
+        assert_eq!(json([Some(1), None]).as_str(), "[1,null]");
+        assert_eq!(json([0u8; 0]).as_str(), "[]");
+    }
+
+    #[test]
+    fn json_nesting() {
+        let mut out = FixedBufWriter::<128>::new();
+        let mut object = JsonObject::new(&mut out);
+        object
+            .object("empty", |_| {})
+            .array("list", |list| {
+                list.object(|item| {
+                    item.field("a", true);
+                })
+                .array(|inner| {
+                    inner.value("x");
+                });
+            })
+            .field("n", 1u8);
+        object.finish().unwrap();
+        assert_eq!(
+            out.as_str(),
+            r#"{"empty":{},"list":[{"a":true},["x"]],"n":1}"#
+        );
+    }
+
+    #[test]
+    fn csv_quotes_only_when_needed() {
+        assert_eq!(
+            csv(&["plain", " spaced ", ""]).as_str(),
+            "plain, spaced ,\n"
+        );
+        assert_eq!(csv(&["a,b"]).as_str(), "\"a,b\"\n");
+        assert_eq!(
+            csv(&["line\nbreak", "cr\r"]).as_str(),
+            "\"line\nbreak\",\"cr\r\"\n"
+        );
+        assert_eq!(csv(&[r#"say "hi""#]).as_str(), "\"say \"\"hi\"\"\"\n");
+        assert_eq!(csv(&["\""]).as_str(), "\"\"\"\"\n");
+    }
+
+    #[test]
+    fn truncation_is_an_error() {
+        let mut out = FixedBufWriter::<16>::new();
+        assert!(crate::alloc_json!(&mut out, "name" => "longer than the buffer").is_err());
+        assert!(out.is_truncated());
+
+        let mut out = FixedBufWriter::<16>::new();
+        let mut object = JsonObject::new(&mut out);
+        object
+            .field("a", 1)
+            .field("b", 2)
+            .field("c", 3)
+            .field("d", 4);
+        assert!(object.finish().is_err());
+
+        let mut out = FixedBufWriter::<8>::new();
+        assert!(crate::alloc_csv!(&mut out, "a,b", "cdef").is_err());
+        assert!(out.is_truncated());
+
+        // Exactly filling the buffer is not truncation.
+        let mut out = FixedBufWriter::<8>::new();
+        assert!(crate::alloc_csv!(&mut out, "abc", "def").is_ok());
+        assert_eq!(out.as_str(), "abc,def\n");
+    }
+}
@@ -0,0 +1,187 @@
+#![feature(allocator_api)]
+use bumpalo::Bump;
+/// Producer threads allocate messages and send them to consumer threads which free
+/// them, so that every block is freed by another thread than the one that
+/// allocated it. This is the slow path of thread-caching allocators (mimalloc's
+/// delayed free, jemalloc's tcache flushes).
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memory_allocator_performance_rs::{
+    ArenaAllocator, DownwardArenaAllocator, GlibcMallocAllocator, JemallocAllocator,
+    MiMallocAllocator, PerThreadAllocator, SbrkAllocator, ShardedAllocator, SpinLockAllocator,
+    ThreadCache, Unshared,
+};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::{Allocator, Layout, System};
+use std::ptr::NonNull;
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::thread;
+
+const TOTAL_MESSAGES: usize = 100_000;
+const CHANNEL_CAPACITY: usize = 1024;
+const SEED: u64 = 42;
+
+/// (producers, consumers)
+const THREAD_RATIOS: [(usize, usize); 5] = [(1, 1), (1, 4), (4, 1), (2, 2), (4, 4)];
+const MAX_MESSAGE_SIZES: [usize; 3] = [64, 1024, 64 * 1024];
+/// Bump allocators hardly reuse freed messages, so they only run the sizes whose
+/// messages all fit in `ARENA_CAPACITY`.
+const BUMP_MAX_MESSAGE_SIZES: [usize; 2] = [64, 1024];
+const ARENA_CAPACITY: usize = 128 * 1024 * 1024;
+
+struct Message {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for Message {}
+
+fn produce<A: Allocator>(
+    allocator: &A,
+    index: usize,
+    messages: usize,
+    max_size: usize,
+    consumers: &[SyncSender<Message>],
+) {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED + index as u64);
+    for i in 0..messages {
+        let size = rng.gen_range(1..=max_size);
+        let layout = Layout::array::<u8>(size).unwrap();
+        let ptr = allocator
+            .allocate(layout)
+            .expect("Allocation failed")
+            .cast::<u8>();
+        unsafe {
+            ptr.as_ptr().write(i as u8);
+            ptr.as_ptr().add(size - 1).write(i as u8);
+        }
+        consumers[i % consumers.len()]
+            .send(Message { ptr, layout })
+            .unwrap();
+    }
+}
+
+fn run_producer_consumer<A: Allocator + Clone + Send + 'static>(
+    allocator: &A,
+    producers: usize,
+    consumers: usize,
+    max_size: usize,
+) {
+    let mut senders = Vec::with_capacity(consumers);
+    let mut handles = Vec::with_capacity(producers + consumers);
+
+    for _ in 0..consumers {
+        let (sender, receiver) = sync_channel::<Message>(CHANNEL_CAPACITY);
+        senders.push(sender);
+        let allocator = allocator.clone();
+        handles.push(thread::spawn(move || {
+            for message in receiver {
+                unsafe {
+                    let ptr = message.ptr.as_ptr();
+                    let last = ptr.add(message.layout.size() - 1);
+                    assert_eq!(ptr.read(), last.read());
+                    allocator.deallocate(message.ptr, message.layout);
+                }
+            }
+        }));
+    }
+
+    for index in 0..producers {
+        let senders = senders.clone();
+        let allocator = allocator.clone();
+        handles.push(thread::spawn(move || {
+            produce(
+                &allocator,
+                index,
+                TOTAL_MESSAGES / producers,
+                max_size,
+                &senders,
+            )
+        }));
+    }
+    // Consumers stop once every producer has dropped its senders.
+    drop(senders);
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+/// Runs every thread ratio and message size in `max_sizes`, with a new allocator
+/// from `make` for every iteration.
+fn bench_allocator<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    max_sizes: &[usize],
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("cross_thread_free");
+    group.sample_size(10);
+    for &(producers, consumers) in &THREAD_RATIOS {
+        for &max_size in max_sizes {
+            let parameter = format!("{}p_{}c_{}B", producers, consumers, max_size);
+            group.bench_function(BenchmarkId::new(allocator_name, parameter), |b| {
+                b.iter_batched_ref(
+                    &make,
+                    |allocator| run_producer_consumer(allocator, producers, consumers, max_size),
+                    criterion::BatchSize::PerIteration,
+                )
+            });
+        }
+    }
+    group.finish();
+}
+
+fn benchmark_allocators(c: &mut Criterion) {
+    bench_allocator(c, "System", &MAX_MESSAGE_SIZES, || System);
+    bench_allocator(c, "GlibcMalloc", &MAX_MESSAGE_SIZES, || {
+        GlibcMallocAllocator
+    });
+    bench_allocator(
+        c,
+        "Jemalloc",
+        &MAX_MESSAGE_SIZES,
+        JemallocAllocator::default,
+    );
+    bench_allocator(c, "MiMalloc", &MAX_MESSAGE_SIZES, || MiMallocAllocator);
+    bench_allocator(c, "GlibcMalloc_thread_cache", &MAX_MESSAGE_SIZES, || {
+        ThreadCache::new(GlibcMallocAllocator)
+    });
+    bench_allocator(c, "GlibcMalloc_per_thread", &MAX_MESSAGE_SIZES, || {
+        PerThreadAllocator::new(|| GlibcMallocAllocator)
+    });
+
+    // The bump allocators are not thread-safe: the new allocators are only ever
+    // used behind the lock.
+    bench_allocator(c, "HeapArena_spin_lock", &BUMP_MAX_MESSAGE_SIZES, || {
+        SpinLockAllocator::new(unsafe {
+            Unshared::new(ArenaAllocator::with_capacity(ARENA_CAPACITY))
+        })
+    });
+    bench_allocator(c, "HeapArena_sharded", &BUMP_MAX_MESSAGE_SIZES, || {
+        ShardedAllocator::new(4, || unsafe {
+            Unshared::new(ArenaAllocator::with_capacity(ARENA_CAPACITY))
+        })
+    });
+    bench_allocator(
+        c,
+        "DownwardArena_spin_lock",
+        &BUMP_MAX_MESSAGE_SIZES,
+        || {
+            SpinLockAllocator::new(unsafe {
+                Unshared::new(DownwardArenaAllocator::with_capacity(ARENA_CAPACITY))
+            })
+        },
+    );
+    // A single lock: sbrk itself is not thread-safe. Memory from sbrk is never
+    // given back, so every iteration moves the break further: small messages only.
+    bench_allocator(c, "Sbrk_spin_lock", &BUMP_MAX_MESSAGE_SIZES[..1], || {
+        SpinLockAllocator::new(unsafe { Unshared::new(SbrkAllocator::new()) })
+    });
+    bench_allocator(c, "bumpallo_spin_lock", &BUMP_MAX_MESSAGE_SIZES, || {
+        SpinLockAllocator::new(unsafe { Unshared::new(Bump::new()) })
+    });
+}
+
+criterion_group!(benches, benchmark_allocators);
+criterion_main!(benches);
@@ -0,0 +1,183 @@
+#![feature(allocator_api)]
+/// Threads allocate small objects at the same time, then hammer them with writes.
+/// If the allocator packs objects of different threads into the same cache line,
+/// the writes bounce the line between cores.
+///
+/// Besides the Criterion throughput, the fraction of object pairs from different
+/// threads sharing a 64-byte line is printed for each allocator.
+use bumpalo::Bump;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memory_allocator_performance_rs::{
+    ArenaAllocator, DownwardArenaAllocator, GlibcMallocAllocator, JemallocAllocator,
+    MiMallocAllocator, PerThreadAllocator, SbrkAllocator, SpinLockAllocator, ThreadCache, Unshared,
+};
+use std::alloc::{Allocator, Layout, System};
+use std::ptr::NonNull;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+const CACHE_LINE: usize = 64;
+const OBJECTS_PER_THREAD: usize = 64;
+const WRITES_PER_OBJECT: usize = 10_000;
+const OBJECT_SIZES: [usize; 3] = [8, 16, 32];
+/// The arenas serve every iteration without reusing freed objects.
+const ARENA_CAPACITY: usize = 256 * 1024 * 1024;
+
+struct Object(NonNull<u8>);
+
+unsafe impl Send for Object {}
+
+/// Allocates `OBJECTS_PER_THREAD` objects on each thread, interleaving the
+/// threads' allocations with a barrier, and returns them grouped by thread.
+fn allocate_objects<A: Allocator + Clone + Send + 'static>(
+    allocator: &A,
+    threads: usize,
+    layout: Layout,
+) -> Vec<Vec<Object>> {
+    let barrier = Arc::new(Barrier::new(threads));
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let allocator = allocator.clone();
+            let barrier = Arc::clone(&barrier);
+            thread::spawn(move || {
+                (0..OBJECTS_PER_THREAD)
+                    .map(|_| {
+                        barrier.wait();
+                        Object(allocator.allocate(layout).unwrap().cast())
+                    })
+                    .collect()
+            })
+        })
+        .collect();
+    handles.into_iter().map(|h| h.join().unwrap()).collect()
+}
+
+fn free_objects<A: Allocator>(allocator: &A, objects: Vec<Vec<Object>>, layout: Layout) {
+    for object in objects.into_iter().flatten() {
+        unsafe { allocator.deallocate(object.0, layout) };
+    }
+}
+
+/// Fraction of pairs of objects owned by different threads that share a cache line.
+fn shared_line_fraction(objects: &[Vec<Object>], layout: Layout) -> f64 {
+    let lines = |object: &Object| {
+        let start = object.0.as_ptr() as usize;
+        (start / CACHE_LINE, (start + layout.size() - 1) / CACHE_LINE)
+    };
+    let mut pairs = 0u64;
+    let mut shared = 0u64;
+    for (i, a_objects) in objects.iter().enumerate() {
+        for b_objects in &objects[i + 1..] {
+            for a in a_objects {
+                let (a_first, a_last) = lines(a);
+                for b in b_objects {
+                    let (b_first, b_last) = lines(b);
+                    pairs += 1;
+                    if a_first <= b_last && b_first <= a_last {
+                        shared += 1;
+                    }
+                }
+            }
+        }
+    }
+    if pairs == 0 {
+        0.0
+    } else {
+        shared as f64 / pairs as f64
+    }
+}
+
+fn write_objects(objects: Vec<Vec<Object>>, layout: Layout) -> Vec<Vec<Object>> {
+    let handles: Vec<_> = objects
+        .into_iter()
+        .map(|objects| {
+            thread::spawn(move || {
+                for _ in 0..WRITES_PER_OBJECT {
+                    for object in &objects {
+                        let ptr = object.0.as_ptr();
+                        for i in 0..layout.size() {
+                            unsafe {
+                                let byte = ptr.add(i);
+                                byte.write_volatile(byte.read_volatile().wrapping_add(1));
+                            }
+                        }
+                    }
+                }
+                objects
+            })
+        })
+        .collect();
+    handles.into_iter().map(|h| h.join().unwrap()).collect()
+}
+
+fn bench_allocator<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    allocator: A,
+) {
+    let threads = std::thread::available_parallelism()
+        .map(|p| p.get())
+        .unwrap_or(1)
+        .max(2);
+    let mut group = c.benchmark_group("false_sharing");
+    group.sample_size(10);
+    for &size in &OBJECT_SIZES {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+
+        let objects = allocate_objects(&allocator, threads, layout);
+        println!(
+            "{}/{}B: {:.2}% of cross-thread object pairs share a cache line",
+            allocator_name,
+            size,
+            shared_line_fraction(&objects, layout) * 100.0
+        );
+        free_objects(&allocator, objects, layout);
+
+        group.bench_function(BenchmarkId::new(allocator_name, size), |b| {
+            b.iter_batched(
+                || allocate_objects(&allocator, threads, layout),
+                |objects| free_objects(&allocator, write_objects(objects, layout), layout),
+                criterion::BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_allocators(c: &mut Criterion) {
+    bench_allocator(c, "System", System);
+    bench_allocator(c, "GlibcMalloc", GlibcMallocAllocator);
+    bench_allocator(c, "Jemalloc", JemallocAllocator::default());
+    bench_allocator(c, "MiMalloc", MiMallocAllocator);
+    bench_allocator(
+        c,
+        "GlibcMalloc_thread_cache",
+        ThreadCache::new(GlibcMallocAllocator),
+    );
+    bench_allocator(
+        c,
+        "GlibcMalloc_per_thread",
+        PerThreadAllocator::new(|| GlibcMallocAllocator),
+    );
+
+    // The bump allocators pack consecutive allocations together whichever thread
+    // makes them. They are not thread-safe: the allocators are only ever used
+    // behind the lock.
+    bench_allocator(c, "HeapArena_spin_lock", unsafe {
+        SpinLockAllocator::new(Unshared::new(ArenaAllocator::with_capacity(ARENA_CAPACITY)))
+    });
+    bench_allocator(c, "DownwardArena_spin_lock", unsafe {
+        SpinLockAllocator::new(Unshared::new(DownwardArenaAllocator::with_capacity(
+            ARENA_CAPACITY,
+        )))
+    });
+    bench_allocator(c, "Sbrk_spin_lock", unsafe {
+        SpinLockAllocator::new(Unshared::new(SbrkAllocator::new()))
+    });
+    bench_allocator(c, "bumpallo_spin_lock", unsafe {
+        SpinLockAllocator::new(Unshared::new(Bump::new()))
+    });
+}
+
+criterion_group!(benches, benchmark_allocators);
+criterion_main!(benches);
@@ -3,7 +3,9 @@
 /// https://github.com/bminor/glibc/tree/master/benchtests
 use bumpalo::Bump;
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use memory_allocator_performance_rs::{GlibcMallocAllocator, JemallocAllocator, MiMallocAllocator};
+use memory_allocator_performance_rs::{
+    GlibcMallocAllocator, JemallocAllocator, MiMallocAllocator, SpinLockAllocator, ThreadCache,
+};
 use std::alloc::{Allocator, Layout, System};
 use std::ptr::{null_mut, NonNull};
 
@@ -89,6 +91,23 @@ fn benchmark_allocators(c: &mut Criterion) {
 
     bench_allocator_multi_thread(c, "System", System);
     bench_allocator_multi_thread(c, "glibc_malloc", &GlibcMallocAllocator);
+    bench_allocator_multi_thread(
+        c,
+        "glibc_malloc_thread_cache",
+        ThreadCache::new(GlibcMallocAllocator),
+    );
+    // A backend serializing every call, standing in for a locked slab
+    // allocator, alone and behind the thread cache.
+    bench_allocator_multi_thread(
+        c,
+        "glibc_malloc_spin_lock",
+        SpinLockAllocator::new(GlibcMallocAllocator),
+    );
+    bench_allocator_multi_thread(
+        c,
+        "glibc_malloc_spin_lock_thread_cache",
+        ThreadCache::new(SpinLockAllocator::new(GlibcMallocAllocator)),
+    );
 
     bench_allocator_multi_thread(c, "Jemalloc", JemallocAllocator::default());
     bench_allocator_multi_thread(c, "MiMalloc", MiMallocAllocator);
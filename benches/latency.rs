@@ -0,0 +1,244 @@
+#![feature(allocator_api)]
+/// Per-operation latency of the `glibc_malloc` and `stress_mem` workloads.
+///
+/// Criterion only measures whole batches, which hides the tail latency of
+/// individual calls. This harness times every allocator call through a
+/// `LatencyAllocator` and prints the percentiles for each allocator.
+use memory_allocator_performance_rs::{
+    GlibcMallocAllocator, Histogram, JemallocAllocator, LatencyAllocator, MiMallocAllocator,
+};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::{Allocator, Layout, System};
+use std::ptr::{null_mut, NonNull};
+use std::thread;
+
+const CHUNKS_TO_ALLOCATE: usize = 1600;
+const SINGLE_THREAD_ITERATIONS: usize = 1000;
+
+const STRING_UNIT: usize = 10;
+const STRING_SIZES: usize = 5;
+const LARGE_UNIT: usize = 1000;
+const LARGE_CHUNK_SIZES: usize = 100;
+const SEED: u64 = 1;
+
+/// Same workload as `single_thread_benchmark` in `glibc_malloc.rs`.
+unsafe fn single_thread_benchmark(size: usize, allocator: &impl Allocator) {
+    let mut chunks: [*mut u8; CHUNKS_TO_ALLOCATE] = [null_mut(); CHUNKS_TO_ALLOCATE];
+    let layout = Layout::from_size_align_unchecked(size, std::mem::align_of::<u8>());
+
+    for a in chunks.iter_mut() {
+        let ptr = allocator
+            .allocate(layout)
+            .expect("Allocation failed")
+            .cast::<u8>()
+            .as_ptr();
+        *a = ptr;
+        for j in 0..size {
+            ptr.add(j).write(j as u8);
+        }
+    }
+
+    for a in chunks.iter().take(CHUNKS_TO_ALLOCATE / 2) {
+        allocator.deallocate(NonNull::new_unchecked(*a), layout);
+    }
+
+    for a in chunks.iter().skip(CHUNKS_TO_ALLOCATE / 2).rev() {
+        allocator.deallocate(NonNull::new_unchecked(*a), layout);
+    }
+}
+
+struct Allocation {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+unsafe impl Send for Allocation {}
+
+/// Same workload as `stress` in `stress_mem.rs`.
+fn stress<A: Allocator>(
+    allocator: &A,
+    allocate_count: usize,
+    retain_count: usize,
+    chunk_size: usize,
+    retained: &mut Vec<Allocation>,
+) {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+    let mut chunk = Vec::new();
+    let mut local_allocate_count = allocate_count;
+    let mut local_retain_count = retain_count;
+
+    while local_retain_count > 0 || local_allocate_count > 0 {
+        if local_retain_count == 0 || (rng.gen::<f32>() < 0.5 && local_allocate_count > 0) {
+            let size = STRING_UNIT * rng.gen_range(1..=STRING_SIZES);
+            let layout = Layout::array::<u8>(size).unwrap();
+            let ptr = allocator
+                .allocate(layout)
+                .expect("Allocation failed")
+                .cast::<u8>();
+            chunk.push(Allocation { ptr, layout });
+            local_allocate_count -= 1;
+            if chunk.len() > chunk_size {
+                for alloc in chunk.drain(..) {
+                    unsafe { allocator.deallocate(alloc.ptr, alloc.layout) };
+                }
+                let layout = Layout::array::<u8>(LARGE_UNIT * LARGE_CHUNK_SIZES).unwrap();
+                let ptr = allocator
+                    .allocate(layout)
+                    .expect("Allocation failed")
+                    .cast::<u8>();
+                chunk.push(Allocation { ptr, layout });
+            }
+        } else {
+            let size = STRING_UNIT * rng.gen_range(1..=STRING_SIZES);
+            let layout = Layout::array::<u8>(size).unwrap();
+            let ptr = allocator
+                .allocate(layout)
+                .expect("Allocation failed")
+                .cast::<u8>();
+            retained.push(Allocation { ptr, layout });
+            local_retain_count -= 1;
+        }
+    }
+
+    for alloc in chunk {
+        unsafe { allocator.deallocate(alloc.ptr, alloc.layout) };
+    }
+}
+
+/// Each thread records into a `fork` of `allocator`, merged back once it is done.
+fn run_stress_test<A: Allocator + Clone + Send + 'static>(
+    allocator: &LatencyAllocator<A>,
+    threads: usize,
+) {
+    let total_allocate_count = 1_000_000;
+    let total_retain_count = 600_000;
+    let total_chunk_size = 200_000;
+
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let allocator = allocator.fork();
+            thread::spawn(move || {
+                let mut retained = Vec::new();
+                stress(
+                    &allocator,
+                    total_allocate_count / threads,
+                    total_retain_count / threads,
+                    total_chunk_size / threads,
+                    &mut retained,
+                );
+                (allocator, retained)
+            })
+        })
+        .collect();
+
+    let mut retained = Vec::new();
+    for handle in handles {
+        let (thread_allocator, thread_retained) = handle.join().unwrap();
+        allocator.merge(&thread_allocator);
+        retained.extend(thread_retained);
+    }
+
+    // Cleanup retained allocations
+    for alloc in retained {
+        unsafe { allocator.deallocate(alloc.ptr, alloc.layout) };
+    }
+}
+
+fn print_header() {
+    println!(
+        "{:<24} {:<12} {:<10} {:>10} {:>8} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        "workload",
+        "allocator",
+        "op",
+        "count",
+        "mean",
+        "p50",
+        "p90",
+        "p99",
+        "p99.9",
+        "p99.99",
+        "max"
+    );
+}
+
+fn print_histogram(workload: &str, allocator_name: &str, op: &str, histogram: &Histogram) {
+    println!(
+        "{:<24} {:<12} {:<10} {:>10} {:>8.1} {:>8} {:>8} {:>8} {:>8} {:>8} {:>10}",
+        workload,
+        allocator_name,
+        op,
+        histogram.count(),
+        histogram.mean(),
+        histogram.percentile(50.0),
+        histogram.percentile(90.0),
+        histogram.percentile(99.0),
+        histogram.percentile(99.9),
+        histogram.percentile(99.99),
+        histogram.max(),
+    );
+}
+
+fn print_report<A: Allocator>(
+    workload: &str,
+    allocator_name: &str,
+    allocator: &LatencyAllocator<A>,
+) {
+    print_histogram(
+        workload,
+        allocator_name,
+        "allocate",
+        allocator.allocations(),
+    );
+    print_histogram(
+        workload,
+        allocator_name,
+        "deallocate",
+        allocator.deallocations(),
+    );
+    if allocator.resizes().count() > 0 {
+        print_histogram(workload, allocator_name, "resize", allocator.resizes());
+    }
+}
+
+fn bench_allocator<A: Allocator + Clone + Send + Sync + 'static>(
+    allocator_name: &str,
+    allocator: A,
+    threads: usize,
+) {
+    for size in [16, 32, 64, 128, 256] {
+        let allocator = LatencyAllocator::new(allocator.clone());
+        for _ in 0..SINGLE_THREAD_ITERATIONS {
+            unsafe { single_thread_benchmark(size, &allocator) };
+        }
+        print_report(
+            &format!("single_thread_{}B", size),
+            allocator_name,
+            &allocator,
+        );
+    }
+
+    let allocator = LatencyAllocator::new(allocator);
+    run_stress_test(&allocator, threads);
+    print_report(
+        &format!("stress_{}_threads", threads),
+        allocator_name,
+        &allocator,
+    );
+}
+
+fn main() {
+    let threads = std::thread::available_parallelism()
+        .map(|p| p.get())
+        .unwrap_or(1);
+    println!(
+        "Latencies in nanoseconds, stress running with {} threads",
+        threads
+    );
+    print_header();
+
+    bench_allocator("System", System, threads);
+    bench_allocator("GlibcMalloc", GlibcMallocAllocator, threads);
+    bench_allocator("Jemalloc", JemallocAllocator::default(), threads);
+    bench_allocator("MiMalloc", MiMallocAllocator, threads);
+}
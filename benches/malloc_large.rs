@@ -14,6 +14,12 @@ const MB: usize = 1024 * 1024;
 const MIN_BUFFER_SIZE: usize = 5 * MB;
 const MAX_BUFFER_SIZE: usize = 25 * MB;
 const NUM_ITERATIONS: usize = 1000;
+/// Touching the buffers is much slower, run fewer iterations for those variants.
+const NUM_TOUCHING_ITERATIONS: usize = 100;
+const PAGE_SIZE: usize = 4096;
+const HUGE_PAGE_SIZE: usize = 2 * MB;
+/// Distance between two touched pages in the sparse variant.
+const SPARSE_STRIDE: usize = 64 * PAGE_SIZE;
 
 #[derive(Clone, Copy)]
 struct Buffer {
@@ -21,11 +27,73 @@ struct Buffer {
     layout: Layout,
 }
 
-fn bench_large_block_allocation<A: Allocator>(allocator: &A) {
+/// How a freshly allocated buffer is used before being freed.
+#[derive(Clone, Copy)]
+enum Access {
+    /// Never touched: only measures the allocator's bookkeeping.
+    Untouched,
+    /// One write per page, paying every page fault.
+    EveryPage,
+    /// One write every `SPARSE_STRIDE` bytes.
+    Sparse,
+    /// Allocated with `allocate_zeroed`, then one write per page.
+    Zeroed,
+    /// Backed by transparent huge pages with `madvise(MADV_HUGEPAGE)`, then one
+    /// write per page.
+    HugePages,
+}
+
+impl Access {
+    const ALL: [Access; 5] = [
+        Access::Untouched,
+        Access::EveryPage,
+        Access::Sparse,
+        Access::Zeroed,
+        Access::HugePages,
+    ];
+
+    fn group_name(self) -> &'static str {
+        match self {
+            Access::Untouched => "large_block_allocation",
+            Access::EveryPage => "large_block_allocation_touch_every_page",
+            Access::Sparse => "large_block_allocation_touch_sparse",
+            Access::Zeroed => "large_block_allocation_zeroed",
+            Access::HugePages => "large_block_allocation_huge_pages",
+        }
+    }
+
+    fn iterations(self) -> usize {
+        match self {
+            Access::Untouched => NUM_ITERATIONS,
+            _ => NUM_TOUCHING_ITERATIONS,
+        }
+    }
+}
+
+fn touch(ptr: NonNull<u8>, size: usize, stride: usize) {
+    for offset in (0..size).step_by(stride) {
+        unsafe { ptr.as_ptr().add(offset).write_volatile(1) };
+    }
+}
+
+/// Asks the kernel to back the 2 MiB-aligned part of the buffer with huge pages.
+#[cfg(target_os = "linux")]
+fn advise_huge_pages(ptr: NonNull<u8>, size: usize) {
+    let start = (ptr.as_ptr() as usize).next_multiple_of(HUGE_PAGE_SIZE);
+    let end = (ptr.as_ptr() as usize + size) & !(HUGE_PAGE_SIZE - 1);
+    if start < end {
+        unsafe { libc::madvise(start as *mut libc::c_void, end - start, libc::MADV_HUGEPAGE) };
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_huge_pages(_ptr: NonNull<u8>, _size: usize) {}
+
+fn bench_large_block_allocation<A: Allocator>(allocator: &A, access: Access) {
     let mut buffers: [Option<Buffer>; NUM_BUFFERS] = [None; NUM_BUFFERS];
     let mut rng = ChaCha8Rng::seed_from_u64(42);
 
-    for _ in 0..NUM_ITERATIONS {
+    for _ in 0..access.iterations() {
         let buffer_idx = rng.gen_range(0..NUM_BUFFERS);
         let new_size = rng.gen_range(MIN_BUFFER_SIZE..=MAX_BUFFER_SIZE);
 
@@ -38,10 +106,22 @@ fn bench_large_block_allocation<A: Allocator>(allocator: &A) {
 
         // Allocate a new buffer
         let layout = Layout::from_size_align(new_size, 1).unwrap();
-        let ptr = allocator
-            .allocate(layout)
-            .expect("Allocation failed")
-            .cast::<u8>();
+        let ptr = match access {
+            Access::Zeroed => allocator.allocate_zeroed(layout),
+            _ => allocator.allocate(layout),
+        }
+        .expect("Allocation failed")
+        .cast::<u8>();
+
+        match access {
+            Access::Untouched => {}
+            Access::EveryPage | Access::Zeroed => touch(ptr, new_size, PAGE_SIZE),
+            Access::Sparse => touch(ptr, new_size, SPARSE_STRIDE),
+            Access::HugePages => {
+                advise_huge_pages(ptr, new_size);
+                touch(ptr, new_size, PAGE_SIZE);
+            }
+        }
 
         buffers[buffer_idx] = Some(Buffer { ptr, layout });
     }
@@ -55,29 +135,34 @@ fn bench_large_block_allocation<A: Allocator>(allocator: &A) {
 }
 
 fn criterion_benchmark(c: &mut Criterion) {
-    let mut group = c.benchmark_group("large_block_allocation");
+    for access in Access::ALL {
+        let mut group = c.benchmark_group(access.group_name());
+        if !matches!(access, Access::Untouched) {
+            group.sample_size(10);
+        }
 
-    group.bench_function("System", |b| {
-        b.iter(|| bench_large_block_allocation(&System))
-    });
+        group.bench_function("System", |b| {
+            b.iter(|| bench_large_block_allocation(&System, access))
+        });
 
-    group.bench_function("GlibcMalloc", |b| {
-        b.iter(|| bench_large_block_allocation(&GlibcMallocAllocator))
-    });
+        group.bench_function("GlibcMalloc", |b| {
+            b.iter(|| bench_large_block_allocation(&GlibcMallocAllocator, access))
+        });
 
-    group.bench_function("Jemalloc", |b| {
-        b.iter(|| bench_large_block_allocation(&JemallocAllocator::default()))
-    });
+        group.bench_function("Jemalloc", |b| {
+            b.iter(|| bench_large_block_allocation(&JemallocAllocator::default(), access))
+        });
 
-    group.bench_function("MiMalloc", |b| {
-        b.iter(|| bench_large_block_allocation(&MiMallocAllocator))
-    });
+        group.bench_function("MiMalloc", |b| {
+            b.iter(|| bench_large_block_allocation(&MiMallocAllocator, access))
+        });
 
-    group.bench_function("bumpallo", |b| {
-        b.iter(|| bench_large_block_allocation(&&Bump::new()))
-    });
+        group.bench_function("bumpallo", |b| {
+            b.iter(|| bench_large_block_allocation(&&Bump::new(), access))
+        });
 
-    group.finish();
+        group.finish();
+    }
 }
 
 criterion_group!(benches, criterion_benchmark);
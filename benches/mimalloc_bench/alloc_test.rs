@@ -0,0 +1,80 @@
+/// node-dot-cpp's alloc-test: every thread keeps a pool of live blocks and
+/// replaces random slots with blocks whose size follows a power-law distribution
+/// (mostly small, sometimes up to 64 KiB). Run with one thread (alloc-test1) and
+/// with all threads (alloc-testN).
+use crate::{threads, Block, SEED};
+use criterion::{BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::thread;
+
+const POOL_SIZE: usize = 1 << 12;
+const ITERATIONS: usize = 100_000;
+const MAX_SIZE_EXPONENT: u32 = 16;
+
+/// Picks an exponent uniformly, then a size uniformly within that power of two.
+fn random_size(rng: &mut ChaCha8Rng) -> usize {
+    let exponent = rng.gen_range(3..MAX_SIZE_EXPONENT);
+    // Favour small sizes: reroll large exponents most of the time.
+    let exponent = if exponent > 10 && rng.gen::<f32>() < 0.9 {
+        rng.gen_range(3..=10)
+    } else {
+        exponent
+    };
+    rng.gen_range((1 << exponent)..(2 << exponent))
+}
+
+fn alloc_test<A: Allocator>(allocator: &A, seed: u64) {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut pool: Vec<Option<Block>> = (0..POOL_SIZE).map(|_| None).collect();
+    for _ in 0..ITERATIONS {
+        let slot = rng.gen_range(0..POOL_SIZE);
+        if let Some(block) = pool[slot].take() {
+            block.free(allocator);
+        } else {
+            let block = Block::allocate(allocator, random_size(&mut rng));
+            unsafe { block.ptr.as_ptr().write(slot as u8) };
+            pool[slot] = Some(block);
+        }
+    }
+    for block in pool.into_iter().flatten() {
+        block.free(allocator);
+    }
+}
+
+fn run_alloc_test<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let handles: Vec<_> = (0..threads)
+        .map(|index| {
+            let allocator = allocator.clone();
+            thread::spawn(move || alloc_test(&allocator, SEED + index as u64))
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("alloc_test");
+    group.sample_size(10);
+    let mut thread_counts = vec![1];
+    if threads() > 1 {
+        thread_counts.push(threads());
+    }
+    for threads in thread_counts {
+        group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+            b.iter_batched_ref(
+                &make,
+                |allocator| run_alloc_test(allocator, threads),
+                criterion::BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
@@ -0,0 +1,68 @@
+/// Hoard's cache-scratch test for passive false sharing: the main thread
+/// allocates one small object per thread, so they are likely to share cache
+/// lines. Each thread frees its object and then repeatedly allocates, writes and
+/// frees objects of the same size. An allocator that hands the freed memory back
+/// to the same thread keeps the threads writing to the same lines.
+use crate::{threads, Block};
+use criterion::{BenchmarkId, Criterion};
+use std::alloc::Allocator;
+use std::thread;
+
+const OBJECT_SIZE: usize = 8;
+const ITERATIONS: usize = 1000;
+const REPETITIONS: usize = 1000;
+
+pub fn write_repeatedly(block: &Block, repetitions: usize) {
+    let ptr = block.ptr.as_ptr();
+    for repetition in 0..repetitions {
+        for i in 0..block.layout.size() {
+            unsafe {
+                let byte = ptr.add(i);
+                byte.write_volatile(byte.read_volatile().wrapping_add(repetition as u8));
+            }
+        }
+    }
+}
+
+fn run_cache_scratch<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let objects: Vec<_> = (0..threads)
+        .map(|_| Block::allocate(allocator, OBJECT_SIZE))
+        .collect();
+
+    let handles: Vec<_> = objects
+        .into_iter()
+        .map(|object| {
+            let allocator = allocator.clone();
+            thread::spawn(move || {
+                object.free(&allocator);
+                for _ in 0..ITERATIONS {
+                    let block = Block::allocate(&allocator, OBJECT_SIZE);
+                    write_repeatedly(&block, REPETITIONS);
+                    block.free(&allocator);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("cache_scratch");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_cache_scratch(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
@@ -0,0 +1,50 @@
+/// Hoard's cache-thrash test for active false sharing: every thread repeatedly
+/// allocates a small object, writes to it and frees it. An allocator that carves
+/// objects for different threads out of the same cache line makes the threads
+/// fight over it.
+use crate::cache_scratch::write_repeatedly;
+use crate::{threads, Block};
+use criterion::{BenchmarkId, Criterion};
+use std::alloc::Allocator;
+use std::thread;
+
+const OBJECT_SIZE: usize = 8;
+const ITERATIONS: usize = 1000;
+const REPETITIONS: usize = 1000;
+
+fn run_cache_thrash<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let handles: Vec<_> = (0..threads)
+        .map(|_| {
+            let allocator = allocator.clone();
+            thread::spawn(move || {
+                for _ in 0..ITERATIONS {
+                    let block = Block::allocate(&allocator, OBJECT_SIZE);
+                    write_repeatedly(&block, REPETITIONS);
+                    block.free(&allocator);
+                }
+            })
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("cache_thrash");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_cache_thrash(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
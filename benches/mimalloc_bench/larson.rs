@@ -0,0 +1,73 @@
+/// Larson & Krishnan's server simulation: every thread owns a set of blocks and
+/// keeps replacing random ones. At the end of each round the sets are handed to a
+/// new generation of threads, which free blocks allocated by the previous one.
+use crate::{threads, Block, SEED};
+use criterion::{BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::thread;
+
+const BLOCKS_PER_THREAD: usize = 1000;
+const REPLACEMENTS_PER_ROUND: usize = 10_000;
+const ROUNDS: usize = 4;
+const MIN_SIZE: usize = 10;
+const MAX_SIZE: usize = 500;
+
+fn run_larson<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+    let mut sets: Vec<Vec<Block>> = (0..threads)
+        .map(|_| {
+            (0..BLOCKS_PER_THREAD)
+                .map(|_| Block::allocate(allocator, rng.gen_range(MIN_SIZE..=MAX_SIZE)))
+                .collect()
+        })
+        .collect();
+
+    for round in 0..ROUNDS {
+        let handles: Vec<_> = sets
+            .into_iter()
+            .enumerate()
+            .map(|(index, mut blocks)| {
+                let allocator = allocator.clone();
+                thread::spawn(move || {
+                    let seed = SEED + (round * threads + index) as u64;
+                    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+                    for _ in 0..REPLACEMENTS_PER_ROUND {
+                        let victim = rng.gen_range(0..blocks.len());
+                        let size = rng.gen_range(MIN_SIZE..=MAX_SIZE);
+                        let block = Block::allocate(&allocator, size);
+                        std::mem::replace(&mut blocks[victim], block).free(&allocator);
+                    }
+                    blocks
+                })
+            })
+            .collect();
+        sets = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+    }
+
+    for block in sets.into_iter().flatten() {
+        block.free(allocator);
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("larson");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_larson(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
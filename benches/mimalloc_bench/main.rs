@@ -0,0 +1,123 @@
+#![feature(allocator_api)]
+/// Ports of the allocator stress tests from
+/// https://github.com/daanx/mimalloc-bench/tree/master/bench
+///
+/// Every workload is scaled down so that one iteration takes a few milliseconds,
+/// and uses deterministic `ChaCha8Rng` seeding.
+mod alloc_test;
+mod cache_scratch;
+mod cache_thrash;
+mod larson;
+mod rptest;
+mod sh6bench;
+mod xmalloc_test;
+
+use bumpalo::Bump;
+use criterion::{criterion_group, criterion_main, Criterion};
+use memory_allocator_performance_rs::{
+    ArenaAllocator, DownwardArenaAllocator, GlibcMallocAllocator, JemallocAllocator,
+    MiMallocAllocator, PerThreadAllocator, SbrkAllocator, ShardedAllocator, SpinLockAllocator,
+    ThreadCache, Unshared,
+};
+use std::alloc::{Allocator, Layout, System};
+use std::ptr::NonNull;
+
+const SEED: u64 = 42;
+/// Enough for an iteration of any workload on all threads, bump allocators
+/// hardly reusing freed blocks.
+const ARENA_CAPACITY: usize = 1024 * 1024 * 1024;
+/// Enough for the blocks of one thread.
+const SHARD_ARENA_CAPACITY: usize = 256 * 1024 * 1024;
+
+/// An allocated block, which can be sent to another thread to be freed there.
+pub struct Block {
+    pub ptr: NonNull<u8>,
+    pub layout: Layout,
+}
+
+unsafe impl Send for Block {}
+
+impl Block {
+    pub fn allocate<A: Allocator>(allocator: &A, size: usize) -> Self {
+        let layout = Layout::array::<u8>(size).unwrap();
+        let ptr = allocator
+            .allocate(layout)
+            .expect("Allocation failed")
+            .cast::<u8>();
+        Block { ptr, layout }
+    }
+
+    pub fn free<A: Allocator>(self, allocator: &A) {
+        unsafe { allocator.deallocate(self.ptr, self.layout) }
+    }
+}
+
+/// Number of threads used by the multi-threaded workloads.
+pub fn threads() -> usize {
+    std::thread::available_parallelism()
+        .map(|p| p.get())
+        .unwrap_or(1)
+        .min(8)
+}
+
+macro_rules! all_allocators {
+    ($name:ident, $bench:path) => {
+        fn $name(c: &mut Criterion) {
+            $bench(c, "System", || System);
+            $bench(c, "GlibcMalloc", || GlibcMallocAllocator);
+            $bench(c, "Jemalloc", JemallocAllocator::default);
+            $bench(c, "MiMalloc", || MiMallocAllocator);
+            $bench(c, "GlibcMalloc_thread_cache", || {
+                ThreadCache::new(GlibcMallocAllocator)
+            });
+            $bench(c, "GlibcMalloc_per_thread", || {
+                PerThreadAllocator::new(|| GlibcMallocAllocator)
+            });
+
+            // The bump allocators are not thread-safe: the new allocators are
+            // only ever used behind the lock.
+            $bench(c, "HeapArena_spin_lock", || {
+                SpinLockAllocator::new(unsafe {
+                    Unshared::new(ArenaAllocator::with_capacity(ARENA_CAPACITY))
+                })
+            });
+            $bench(c, "HeapArena_sharded", || {
+                ShardedAllocator::new(threads(), || unsafe {
+                    Unshared::new(ArenaAllocator::with_capacity(SHARD_ARENA_CAPACITY))
+                })
+            });
+            $bench(c, "DownwardArena_spin_lock", || {
+                SpinLockAllocator::new(unsafe {
+                    Unshared::new(DownwardArenaAllocator::with_capacity(ARENA_CAPACITY))
+                })
+            });
+            // A single lock: sbrk itself is not thread-safe.
+            $bench(c, "Sbrk_spin_lock", || {
+                SpinLockAllocator::new(unsafe { Unshared::new(SbrkAllocator::new()) })
+            });
+            $bench(c, "bumpallo_spin_lock", || {
+                SpinLockAllocator::new(unsafe { Unshared::new(Bump::new()) })
+            });
+        }
+    };
+}
+
+all_allocators!(bench_larson, larson::bench);
+all_allocators!(bench_cache_scratch, cache_scratch::bench);
+all_allocators!(bench_cache_thrash, cache_thrash::bench);
+all_allocators!(bench_xmalloc_test, xmalloc_test::bench);
+all_allocators!(bench_alloc_test, alloc_test::bench);
+all_allocators!(bench_rptest, rptest::bench);
+all_allocators!(bench_sh6bench, sh6bench::bench);
+
+criterion_group!(
+    benches,
+    bench_larson,
+    bench_cache_scratch,
+    bench_cache_thrash,
+    bench_xmalloc_test,
+    bench_alloc_test,
+    bench_rptest,
+    bench_sh6bench
+);
+criterion_main!(benches);
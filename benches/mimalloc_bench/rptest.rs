@@ -0,0 +1,75 @@
+/// rpmalloc's rptest: threads form a ring. Each loop, a thread allocates a batch
+/// of blocks of mixed sizes, frees half of them itself and hands the other half
+/// to the next thread in the ring, then frees what it received from the previous
+/// one.
+use crate::{threads, Block, SEED};
+use criterion::{BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::sync::mpsc::channel;
+use std::thread;
+
+const LOOPS: usize = 200;
+const BATCH_SIZE: usize = 256;
+const MIN_SIZE: usize = 16;
+const MAX_SIZE: usize = 8000;
+
+fn run_rptest<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let (senders, receivers): (Vec<_>, Vec<_>) =
+        (0..threads).map(|_| channel::<Vec<Block>>()).unzip();
+
+    let handles: Vec<_> = receivers
+        .into_iter()
+        .enumerate()
+        .map(|(index, receiver)| {
+            let allocator = allocator.clone();
+            let next = senders[(index + 1) % threads].clone();
+            thread::spawn(move || {
+                let mut rng = ChaCha8Rng::seed_from_u64(SEED + index as u64);
+                for _ in 0..LOOPS {
+                    let mut batch: Vec<_> = (0..BATCH_SIZE)
+                        .map(|_| {
+                            // Mostly small blocks, like the original size distribution.
+                            let size = if rng.gen::<f32>() < 0.8 {
+                                rng.gen_range(MIN_SIZE..=256)
+                            } else {
+                                rng.gen_range(MIN_SIZE..=MAX_SIZE)
+                            };
+                            Block::allocate(&allocator, size)
+                        })
+                        .collect();
+                    let handed_over = batch.split_off(BATCH_SIZE / 2);
+                    batch.into_iter().for_each(|block| block.free(&allocator));
+                    next.send(handed_over).unwrap();
+                    for block in receiver.recv().unwrap() {
+                        block.free(&allocator);
+                    }
+                }
+            })
+        })
+        .collect();
+    drop(senders);
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("rptest");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_rptest(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
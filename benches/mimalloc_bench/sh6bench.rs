@@ -0,0 +1,82 @@
+/// MicroQuill's SmartHeap sh6bench: every thread allocates arrays of blocks of
+/// increasing sizes and frees them in LIFO, FIFO and interleaved orders, mixed
+/// with a few long-lived blocks that fragment the heap.
+use crate::{threads, Block, SEED};
+use criterion::{BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::thread;
+
+const BLOCKS_PER_SIZE: usize = 1000;
+const MIN_SIZE: usize = 1;
+const MAX_SIZE: usize = 1000;
+const SIZE_STEP: usize = 37;
+
+fn sh6bench<A: Allocator>(allocator: &A, seed: u64) {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let mut long_lived = Vec::new();
+
+    for size in (MIN_SIZE..=MAX_SIZE).step_by(SIZE_STEP) {
+        // LIFO
+        let blocks: Vec<_> = (0..BLOCKS_PER_SIZE)
+            .map(|_| Block::allocate(allocator, size))
+            .collect();
+        blocks
+            .into_iter()
+            .rev()
+            .for_each(|block| block.free(allocator));
+
+        // Every other block first, then the remaining ones in FIFO order
+        let blocks: Vec<_> = (0..BLOCKS_PER_SIZE)
+            .map(|_| Block::allocate(allocator, size))
+            .collect();
+        let (odd, even): (Vec<_>, Vec<_>) = blocks
+            .into_iter()
+            .enumerate()
+            .partition(|(i, _)| i % 2 == 1);
+        odd.into_iter().for_each(|(_, block)| block.free(allocator));
+        even.into_iter()
+            .for_each(|(_, block)| block.free(allocator));
+
+        long_lived.push(Block::allocate(
+            allocator,
+            rng.gen_range(MIN_SIZE..=MAX_SIZE),
+        ));
+    }
+
+    long_lived
+        .into_iter()
+        .for_each(|block| block.free(allocator));
+}
+
+fn run_sh6bench<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let handles: Vec<_> = (0..threads)
+        .map(|index| {
+            let allocator = allocator.clone();
+            thread::spawn(move || sh6bench(&allocator, SEED + index as u64))
+        })
+        .collect();
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("sh6bench");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_sh6bench(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
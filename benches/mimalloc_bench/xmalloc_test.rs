@@ -0,0 +1,76 @@
+/// Lever & Boreham's xmalloc-test: half of the threads allocate batches of small
+/// objects and push them on a shared queue, the other half pop the batches and
+/// free them, so nearly every free happens on a foreign thread.
+use crate::{threads, Block, SEED};
+use criterion::{BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::sync::mpsc::{channel, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+const TOTAL_OBJECTS: usize = 200_000;
+const BATCH_SIZE: usize = 100;
+const SIZES: [usize; 8] = [8, 16, 24, 32, 48, 64, 96, 128];
+
+fn free_batches<A: Allocator>(allocator: &A, batches: &Mutex<Receiver<Vec<Block>>>) {
+    loop {
+        // Only hold the lock while receiving, not while freeing.
+        let batch = batches.lock().unwrap().recv();
+        match batch {
+            Ok(batch) => batch.into_iter().for_each(|block| block.free(allocator)),
+            Err(_) => return,
+        }
+    }
+}
+
+fn run_xmalloc_test<A: Allocator + Clone + Send + 'static>(allocator: &A, threads: usize) {
+    let allocating_threads = (threads / 2).max(1);
+    let freeing_threads = (threads - allocating_threads).max(1);
+    let (sender, receiver) = channel::<Vec<Block>>();
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    let mut handles = Vec::with_capacity(allocating_threads + freeing_threads);
+    for _ in 0..freeing_threads {
+        let allocator = allocator.clone();
+        let receiver = Arc::clone(&receiver);
+        handles.push(thread::spawn(move || free_batches(&allocator, &receiver)));
+    }
+    for index in 0..allocating_threads {
+        let allocator = allocator.clone();
+        let sender = sender.clone();
+        handles.push(thread::spawn(move || {
+            let mut rng = ChaCha8Rng::seed_from_u64(SEED + index as u64);
+            for _ in 0..TOTAL_OBJECTS / allocating_threads / BATCH_SIZE {
+                let batch = (0..BATCH_SIZE)
+                    .map(|_| Block::allocate(&allocator, *SIZES.choose(&mut rng).unwrap()))
+                    .collect();
+                sender.send(batch).unwrap();
+            }
+        }));
+    }
+    drop(sender);
+
+    for handle in handles {
+        handle.join().unwrap();
+    }
+}
+
+pub fn bench<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    make: impl Fn() -> A,
+) {
+    let mut group = c.benchmark_group("xmalloc_test");
+    group.sample_size(10);
+    let threads = threads();
+    group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| run_xmalloc_test(allocator, threads),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
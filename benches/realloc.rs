@@ -0,0 +1,162 @@
+#![feature(allocator_api)]
+/// Workloads dominated by `grow` and `shrink`, to compare in-place realloc
+/// strategies: geometric and linear growth, shrinking back, and growth
+/// interleaved with allocations that block in-place extension.
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memory_allocator_performance_rs::{
+    ArenaAllocator, GlibcMallocAllocator, JemallocAllocator, MiMallocAllocator,
+};
+use std::alloc::{Allocator, Layout, System};
+use std::io::Write;
+use std::ptr::NonNull;
+
+const MB: usize = 1024 * 1024;
+/// `Vec<u64>` pushes up to 32 MiB.
+const VEC_ELEMENTS: usize = 4 * MB;
+const STRING_SIZE: usize = MB;
+const LINEAR_STEP: usize = 4096;
+const LINEAR_MAX_SIZE: usize = 4 * MB;
+const DOUBLING_MAX_SIZE: usize = 32 * MB;
+const INTERLEAVED_MAX_SIZE: usize = 8 * MB;
+const ARENA_CAPACITY: usize = 512 * MB;
+
+/// Amortized doubling through `Vec::push`.
+fn vec_push<A: Allocator>(allocator: &A) {
+    let mut v = Vec::new_in(allocator);
+    for i in 0..VEC_ELEMENTS {
+        v.push(i as u64);
+    }
+    criterion::black_box(&v);
+}
+
+/// Builds a string out of small fragments.
+fn string_building<A: Allocator>(allocator: &A) {
+    let mut s = Vec::new_in(allocator);
+    let mut i = 0usize;
+    // Numbers are formatted on the stack, only `s` goes through `allocator`.
+    let mut digits = [0u8; 20];
+    while s.len() < STRING_SIZE {
+        s.extend_from_slice(b"key_");
+        let mut cursor = &mut digits[..];
+        write!(cursor, "{}", i).unwrap();
+        let unused = cursor.len();
+        s.extend_from_slice(&digits[..digits.len() - unused]);
+        s.push(b',');
+        i += 1;
+    }
+    criterion::black_box(&s);
+}
+
+struct Buffer<'a, A: Allocator> {
+    allocator: &'a A,
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl<'a, A: Allocator> Buffer<'a, A> {
+    fn new(allocator: &'a A, size: usize) -> Self {
+        let layout = Layout::array::<u8>(size).unwrap();
+        let ptr = allocator.allocate(layout).unwrap().cast();
+        Buffer {
+            allocator,
+            ptr,
+            layout,
+        }
+    }
+
+    fn resize(&mut self, size: usize) {
+        let new_layout = Layout::array::<u8>(size).unwrap();
+        let new_ptr = unsafe {
+            if size >= self.layout.size() {
+                self.allocator.grow(self.ptr, self.layout, new_layout)
+            } else {
+                self.allocator.shrink(self.ptr, self.layout, new_layout)
+            }
+        };
+        self.ptr = new_ptr.unwrap().cast();
+        self.layout = new_layout;
+        // Touch the end so that the buffer is really backed by memory.
+        unsafe { self.ptr.as_ptr().add(size - 1).write(1) };
+    }
+}
+
+impl<A: Allocator> Drop for Buffer<'_, A> {
+    fn drop(&mut self) {
+        unsafe { self.allocator.deallocate(self.ptr, self.layout) }
+    }
+}
+
+/// Grows a buffer by a fixed step, the worst case for allocators that copy.
+fn linear_growth<A: Allocator>(allocator: &A) {
+    let mut buffer = Buffer::new(allocator, LINEAR_STEP);
+    for size in (2 * LINEAR_STEP..=LINEAR_MAX_SIZE).step_by(LINEAR_STEP) {
+        buffer.resize(size);
+    }
+}
+
+/// Doubles a buffer up to tens of MiB, then halves it back down.
+fn doubling_then_shrinking<A: Allocator>(allocator: &A) {
+    let mut size = 16;
+    let mut buffer = Buffer::new(allocator, size);
+    while size < DOUBLING_MAX_SIZE {
+        size *= 2;
+        buffer.resize(size);
+    }
+    while size > 16 {
+        size /= 2;
+        buffer.resize(size);
+    }
+}
+
+/// Grows two buffers in turn with a small allocation in between, so that neither
+/// can simply extend into the free space after it.
+fn interleaved_growth<A: Allocator>(allocator: &A) {
+    let mut size = 64;
+    let mut a = Buffer::new(allocator, size);
+    let mut b = Buffer::new(allocator, size);
+    let mut blockers = Vec::new();
+    while size < INTERLEAVED_MAX_SIZE {
+        size += size / 2;
+        a.resize(size);
+        blockers.push(Buffer::new(allocator, 32));
+        b.resize(size);
+        blockers.push(Buffer::new(allocator, 32));
+    }
+}
+
+type Workload<A> = fn(&A);
+
+fn bench_allocator<A: Allocator>(c: &mut Criterion, allocator_name: &str, make: impl Fn() -> A) {
+    let workloads: [(&str, Workload<A>); 5] = [
+        ("vec_push", vec_push),
+        ("string_building", string_building),
+        ("linear_growth", linear_growth),
+        ("doubling_then_shrinking", doubling_then_shrinking),
+        ("interleaved_growth", interleaved_growth),
+    ];
+    let mut group = c.benchmark_group("realloc");
+    group.sample_size(10);
+    for (workload_name, workload) in workloads {
+        group.bench_function(BenchmarkId::new(allocator_name, workload_name), |b| {
+            b.iter_batched_ref(
+                &make,
+                |allocator| workload(allocator),
+                criterion::BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn benchmark_allocators(c: &mut Criterion) {
+    bench_allocator(c, "System", || System);
+    bench_allocator(c, "GlibcMalloc", || GlibcMallocAllocator);
+    bench_allocator(c, "Jemalloc", JemallocAllocator::default);
+    bench_allocator(c, "MiMalloc", || MiMallocAllocator);
+    bench_allocator(c, "HeapArena_512MB", || {
+        ArenaAllocator::with_capacity(ARENA_CAPACITY)
+    });
+}
+
+criterion_group!(benches, benchmark_allocators);
+criterion_main!(benches);
@@ -1,7 +1,10 @@
 #![feature(allocator_api)]
 
-use criterion::{criterion_group, criterion_main, Criterion};
-use memory_allocator_performance_rs::{GlibcMallocAllocator, JemallocAllocator, MiMallocAllocator};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use memory_allocator_performance_rs::{
+    GlibcMallocAllocator, JemallocAllocator, MiMallocAllocator, PerThreadAllocator,
+    ShardedAllocator, SharedAllocator, SpinLockAllocator,
+};
 use rand::prelude::*;
 use rand_chacha::ChaCha8Rng;
 use std::alloc::{Allocator, Layout, System};
@@ -14,24 +17,6 @@ const LARGE_UNIT: usize = 1000;
 const LARGE_CHUNK_SIZES: usize = 100;
 const SEED: u64 = 1;
 
-struct ThreadSafeAllocator<A: Allocator>(Arc<Mutex<A>>);
-
-unsafe impl<A: Allocator> Allocator for ThreadSafeAllocator<A> {
-    fn allocate(&self, layout: Layout) -> Result<std::ptr::NonNull<[u8]>, std::alloc::AllocError> {
-        self.0.lock().unwrap().allocate(layout)
-    }
-
-    unsafe fn deallocate(&self, ptr: std::ptr::NonNull<u8>, layout: Layout) {
-        self.0.lock().unwrap().deallocate(ptr, layout)
-    }
-}
-
-impl<A: Allocator> Clone for ThreadSafeAllocator<A> {
-    fn clone(&self) -> Self {
-        ThreadSafeAllocator(Arc::clone(&self.0))
-    }
-}
-
 struct Allocation {
     ptr: std::ptr::NonNull<u8>,
     layout: Layout,
@@ -95,12 +80,11 @@ fn stress<A: Allocator>(
     }
 }
 
-fn run_stress_test<A: Allocator + Clone + Send + Sync + 'static>(allocator: A, threads: usize) {
+fn run_stress_test<A: Allocator + Clone + Send + 'static>(allocator: A, threads: usize) {
     let total_allocate_count = 1_000_000;
     let total_retain_count = 600_000;
     let total_chunk_size = 200_000;
 
-    let allocator = ThreadSafeAllocator(Arc::new(Mutex::new(allocator)));
     let retained = Arc::new(Mutex::new(Vec::new()));
 
     let handles: Vec<_> = (0..threads)
@@ -130,38 +114,72 @@ fn run_stress_test<A: Allocator + Clone + Send + Sync + 'static>(allocator: A, t
     let mut retained = retained.lock().unwrap();
     for alloc in retained.drain(..) {
         unsafe {
-            allocator
-                .0
-                .lock()
-                .unwrap()
-                .deallocate(alloc.ptr, alloc.layout);
+            allocator.deallocate(alloc.ptr, alloc.layout);
         }
     }
 }
 
+fn bench_allocator<A: Allocator + Clone + Send + 'static>(
+    c: &mut Criterion,
+    allocator_name: &str,
+    allocator: A,
+    max_threads: usize,
+) {
+    let mut group = c.benchmark_group("mixed_allocation_stress_test");
+    group.sample_size(10);
+    for threads in 1..=max_threads {
+        group.bench_function(BenchmarkId::new(allocator_name, threads), |b| {
+            b.iter(|| run_stress_test(allocator.clone(), threads))
+        });
+    }
+    group.finish();
+}
+
 fn criterion_benchmark(c: &mut Criterion) {
-    let threads = std::thread::available_parallelism()
+    let max_threads = std::thread::available_parallelism()
         .map(|p| p.get())
         .unwrap_or(1);
-    println!("Running with {} threads", threads);
-
-    let mut group = c.benchmark_group("mixed_allocation_stress_test");
-
-    group.bench_function("System", |b| b.iter(|| run_stress_test(System, threads)));
-
-    group.bench_function("GlibcMalloc", |b| {
-        b.iter(|| run_stress_test(GlibcMallocAllocator, threads))
-    });
-
-    group.bench_function("Jemalloc", |b| {
-        b.iter(|| run_stress_test(JemallocAllocator::default(), threads))
-    });
-
-    group.bench_function("MiMalloc", |b| {
-        b.iter(|| run_stress_test(MiMallocAllocator, threads))
-    });
-
-    group.finish();
+    println!("Running with 1 to {} threads", max_threads);
+
+    bench_allocator(c, "System", SharedAllocator::new(System), max_threads);
+    bench_allocator(
+        c,
+        "GlibcMalloc",
+        SharedAllocator::new(GlibcMallocAllocator),
+        max_threads,
+    );
+    bench_allocator(
+        c,
+        "Jemalloc",
+        SharedAllocator::new(JemallocAllocator::default()),
+        max_threads,
+    );
+    bench_allocator(
+        c,
+        "MiMalloc",
+        SharedAllocator::new(MiMallocAllocator),
+        max_threads,
+    );
+
+    // Locking strategies over the same allocator, to measure their own overhead.
+    bench_allocator(
+        c,
+        "GlibcMalloc_spin_lock",
+        SpinLockAllocator::new(GlibcMallocAllocator),
+        max_threads,
+    );
+    bench_allocator(
+        c,
+        "GlibcMalloc_sharded",
+        ShardedAllocator::new(4, || GlibcMallocAllocator),
+        max_threads,
+    );
+    bench_allocator(
+        c,
+        "GlibcMalloc_per_thread",
+        PerThreadAllocator::new(|| GlibcMallocAllocator),
+        max_threads,
+    );
 }
 
 criterion_group!(benches, criterion_benchmark);
@@ -5,7 +5,8 @@ use criterion::{
     criterion_group, criterion_main, measurement::WallTime, BenchmarkGroup, BenchmarkId, Criterion,
 };
 use memory_allocator_performance_rs::{
-    ArenaAllocator, JemallocAllocator, MiMallocAllocator, SbrkAllocator,
+    ArenaAllocator, DownwardArenaAllocator, Fallback, JemallocAllocator, MiMallocAllocator,
+    SbrkAllocator,
 };
 use std::{
     alloc::{Allocator, Layout, System},
@@ -51,12 +52,6 @@ fn bench_allocator(c: &mut Criterion, allocator_name: &str, allocator: impl Allo
 fn benchmark_allocators(c: &mut Criterion) {
     // bench_allocator(c, "System", System);
 
-    // bench_allocator(
-    //     c,
-    //     "HeapArena_8MB",
-    //     ArenaAllocator::with_capacity(8 * 1024 * 1024),
-    // );
-
     // static mut STATIC_ARENA_MEM: [u8; 8 * 1024 * 1024] = [0; 8 * 1024 * 1024];
     // bench_allocator(
     //     c,
@@ -65,7 +60,27 @@ fn benchmark_allocators(c: &mut Criterion) {
     // );
     // bench_allocator(c, "JemallocAllocator", JemallocAllocator::default());
     // bench_allocator(c, "MiMallocAllocator", MiMallocAllocator);
-    // bench_allocator(c, "bumpalloAllocator", &Bump::new());
+    bench_allocator(c, "bumpalloAllocator", &Bump::new());
+    bench_allocator(
+        c,
+        "HeapArena_8MB",
+        ArenaAllocator::with_capacity(8 * 1024 * 1024),
+    );
+    bench_allocator(
+        c,
+        "DownwardArena_8MB",
+        DownwardArenaAllocator::with_capacity(8 * 1024 * 1024),
+    );
+    // Each block is freed right away and taken back by the arena, so mimalloc is
+    // never reached: this measures the cost of the ownership check.
+    bench_allocator(
+        c,
+        "ArenaFallbackMiMalloc_8MB",
+        Fallback::new(
+            ArenaAllocator::with_capacity(8 * 1024 * 1024),
+            MiMallocAllocator,
+        ),
+    );
     let sbrk_allocator = SbrkAllocator::new();
     sbrk_allocator.increase_heap_size(4096).unwrap();
     bench_allocator(c, "sbrkAllocator", sbrk_allocator);
@@ -0,0 +1,52 @@
+/// An ordered index: a `BTreeMap` with heap-allocated values under inserts,
+/// range scans and removals.
+use crate::SEED;
+use criterion::{black_box, BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::collections::BTreeMap;
+
+const OPERATIONS: usize = 200_000;
+const KEY_SPACE: u64 = 100_000;
+const RANGE_LENGTH: u64 = 100;
+
+fn btree_workload<A: Allocator + Clone>(allocator: A) {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+    let mut map = BTreeMap::new_in(allocator.clone());
+    let mut scanned = 0usize;
+
+    for _ in 0..OPERATIONS {
+        let key = rng.gen_range(0..KEY_SPACE);
+        match rng.gen_range(0..10) {
+            0..=5 => {
+                let mut value = Vec::with_capacity_in(rng.gen_range(8..128), allocator.clone());
+                value.extend_from_slice(&key.to_le_bytes());
+                map.insert(key, value);
+            }
+            6..=7 => {
+                map.remove(&key);
+            }
+            _ => {
+                scanned += map
+                    .range(key..key + RANGE_LENGTH)
+                    .map(|(_, value)| value.len())
+                    .sum::<usize>();
+            }
+        }
+    }
+    black_box(scanned);
+}
+
+pub fn bench<A: Allocator>(c: &mut Criterion, allocator_name: &str, make: impl Fn() -> A) {
+    let mut group = c.benchmark_group("btree");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new(allocator_name, OPERATIONS), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| btree_workload(&*allocator),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
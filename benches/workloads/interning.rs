@@ -0,0 +1,65 @@
+/// A string interner: a hash map from string keys to ids, with keys constantly
+/// removed and re-inserted.
+use crate::SEED;
+use criterion::{black_box, BenchmarkId, Criterion};
+use hashbrown::{DefaultHashBuilder, HashMap};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+use std::io::Write;
+
+const INITIAL_KEYS: usize = 50_000;
+const CHURN_OPERATIONS: usize = 200_000;
+/// Keys are drawn from a larger space than the live set, so lookups both hit and miss.
+const KEY_SPACE: u64 = 4 * INITIAL_KEYS as u64;
+
+type Key<'a, A> = Box<[u8], &'a A>;
+
+fn make_key<A: Allocator>(allocator: &A, id: u64) -> Key<'_, A> {
+    let mut key = Vec::new_in(allocator);
+    write!(
+        key,
+        "identifier_{}_{:x}",
+        id,
+        id.wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    )
+    .unwrap();
+    key.into_boxed_slice()
+}
+
+fn intern<A: Allocator>(allocator: &A) {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+    let mut interner: HashMap<Key<'_, A>, u32, DefaultHashBuilder, &A> = HashMap::new_in(allocator);
+    let mut next_id = 0u32;
+
+    for _ in 0..INITIAL_KEYS {
+        interner.insert(make_key(allocator, rng.gen_range(0..KEY_SPACE)), next_id);
+        next_id += 1;
+    }
+
+    for _ in 0..CHURN_OPERATIONS {
+        let key = make_key(allocator, rng.gen_range(0..KEY_SPACE));
+        if rng.gen_bool(0.5) {
+            interner.remove(&key[..]);
+        } else {
+            interner.entry(key).or_insert_with(|| {
+                next_id += 1;
+                next_id
+            });
+        }
+    }
+    black_box(interner.len());
+}
+
+pub fn bench<A: Allocator>(c: &mut Criterion, allocator_name: &str, make: impl Fn() -> A) {
+    let mut group = c.benchmark_group("string_interning");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new(allocator_name, CHURN_OPERATIONS), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| intern(allocator),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
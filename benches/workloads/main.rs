@@ -0,0 +1,57 @@
+#![feature(allocator_api)]
+#![feature(btreemap_alloc)]
+/// Self-contained reproductions of typical application allocation patterns, each
+/// parameterized by the allocator through the `allocator_api`.
+mod btree;
+mod interning;
+mod parser;
+mod tree;
+
+use bumpalo::Bump;
+use criterion::{criterion_group, criterion_main, Criterion};
+use memory_allocator_performance_rs::{
+    ArenaAllocator, DownwardArenaAllocator, GlibcMallocAllocator, JemallocAllocator,
+    MiMallocAllocator, SbrkAllocator, ThreadCache, Unshared,
+};
+use std::alloc::System;
+
+const SEED: u64 = 42;
+const ARENA_CAPACITY: usize = 1024 * 1024 * 1024;
+
+macro_rules! all_allocators {
+    ($name:ident, $bench:path) => {
+        fn $name(c: &mut Criterion) {
+            $bench(c, "System", || System);
+            $bench(c, "GlibcMalloc", || GlibcMallocAllocator);
+            $bench(c, "Jemalloc", JemallocAllocator::default);
+            $bench(c, "MiMalloc", || MiMallocAllocator);
+            let thread_cache = ThreadCache::new(GlibcMallocAllocator);
+            $bench(c, "GlibcMalloc_thread_cache", || thread_cache.clone());
+            // A fresh arena per iteration, since it never reuses memory.
+            $bench(c, "HeapArena_1GB", || {
+                ArenaAllocator::with_capacity(ARENA_CAPACITY)
+            });
+            $bench(c, "DownwardArena_1GB", || {
+                DownwardArenaAllocator::with_capacity(ARENA_CAPACITY)
+            });
+            // Gives its memory back when dropped after the iteration.
+            $bench(c, "Sbrk", SbrkAllocator::new);
+            // Owns the `Bump`, which is only used by the iteration.
+            $bench(c, "bumpallo", || unsafe { Unshared::new(Bump::new()) });
+        }
+    };
+}
+
+all_allocators!(bench_tree, tree::bench);
+all_allocators!(bench_interning, interning::bench);
+all_allocators!(bench_parser, parser::bench);
+all_allocators!(bench_btree, btree::bench);
+
+criterion_group!(
+    benches,
+    bench_tree,
+    bench_interning,
+    bench_parser,
+    bench_btree
+);
+criterion_main!(benches);
@@ -0,0 +1,205 @@
+/// A recursive-descent JSON parser building an AST whose strings, arrays and
+/// objects all live in the benchmarked allocator. The document is generated once
+/// up front, so only parsing and dropping the tree are measured.
+use crate::SEED;
+use criterion::{black_box, BenchmarkId, Criterion};
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::alloc::Allocator;
+
+const MAX_DEPTH: u32 = 6;
+const MAX_CHILDREN: usize = 8;
+const DOCUMENTS: usize = 2000;
+
+#[allow(dead_code)]
+enum Value<'a, A: Allocator> {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(Vec<u8, &'a A>),
+    Array(Vec<Value<'a, A>, &'a A>),
+    Object(Vec<(Vec<u8, &'a A>, Value<'a, A>), &'a A>),
+}
+
+struct Parser<'a, 'i, A: Allocator> {
+    allocator: &'a A,
+    input: &'i [u8],
+    position: usize,
+}
+
+impl<'a, A: Allocator> Parser<'a, '_, A> {
+    fn peek(&self) -> u8 {
+        self.input[self.position]
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_ascii_whitespace() {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) {
+        self.skip_whitespace();
+        assert_eq!(self.peek(), byte, "unexpected byte at {}", self.position);
+        self.position += 1;
+    }
+
+    fn parse_value(&mut self) -> Value<'a, A> {
+        self.skip_whitespace();
+        match self.peek() {
+            b'n' => self.parse_keyword(b"null", Value::Null),
+            b't' => self.parse_keyword(b"true", Value::Bool(true)),
+            b'f' => self.parse_keyword(b"false", Value::Bool(false)),
+            b'"' => Value::String(self.parse_string()),
+            b'[' => self.parse_array(),
+            b'{' => self.parse_object(),
+            _ => self.parse_number(),
+        }
+    }
+
+    fn parse_keyword(&mut self, keyword: &[u8], value: Value<'a, A>) -> Value<'a, A> {
+        assert!(self.input[self.position..].starts_with(keyword));
+        self.position += keyword.len();
+        value
+    }
+
+    fn parse_number(&mut self) -> Value<'a, A> {
+        let start = self.position;
+        while matches!(self.peek(), b'-' | b'+' | b'.' | b'e' | b'E' | b'0'..=b'9') {
+            self.position += 1;
+        }
+        let text = std::str::from_utf8(&self.input[start..self.position]).unwrap();
+        Value::Number(text.parse().unwrap())
+    }
+
+    fn parse_string(&mut self) -> Vec<u8, &'a A> {
+        self.expect(b'"');
+        let mut string = Vec::new_in(self.allocator);
+        loop {
+            match self.peek() {
+                b'"' => break,
+                b'\\' => {
+                    self.position += 1;
+                    string.push(match self.peek() {
+                        b'n' => b'\n',
+                        b't' => b'\t',
+                        escaped => escaped,
+                    });
+                }
+                byte => string.push(byte),
+            }
+            self.position += 1;
+        }
+        self.position += 1;
+        string
+    }
+
+    /// Parses comma-separated elements up to `close`, the opening bracket having
+    /// been consumed already.
+    fn parse_elements(&mut self, close: u8, mut element: impl FnMut(&mut Self)) {
+        self.skip_whitespace();
+        if self.peek() == close {
+            self.position += 1;
+            return;
+        }
+        loop {
+            element(self);
+            self.skip_whitespace();
+            if self.peek() == close {
+                self.position += 1;
+                return;
+            }
+            self.expect(b',');
+        }
+    }
+
+    fn parse_array(&mut self) -> Value<'a, A> {
+        self.expect(b'[');
+        let mut elements = Vec::new_in(self.allocator);
+        self.parse_elements(b']', |parser| elements.push(parser.parse_value()));
+        Value::Array(elements)
+    }
+
+    fn parse_object(&mut self) -> Value<'a, A> {
+        self.expect(b'{');
+        let mut members = Vec::new_in(self.allocator);
+        self.parse_elements(b'}', |parser| {
+            let key = parser.parse_string();
+            parser.expect(b':');
+            members.push((key, parser.parse_value()));
+        });
+        Value::Object(members)
+    }
+}
+
+fn generate_value(rng: &mut ChaCha8Rng, depth: u32, out: &mut String) {
+    let container = depth < MAX_DEPTH && rng.gen_bool(0.4);
+    match (container, rng.gen_range(0..4)) {
+        (true, 0 | 1) => {
+            out.push('[');
+            for i in 0..rng.gen_range(0..MAX_CHILDREN) {
+                if i > 0 {
+                    out.push_str(", ");
+                }
+                generate_value(rng, depth + 1, out);
+            }
+            out.push(']');
+        }
+        (true, _) => {
+            out.push('{');
+            for i in 0..rng.gen_range(0..MAX_CHILDREN) {
+                if i > 0 {
+                    out.push(',');
+                }
+                out.push_str(&format!("\n\"field_{}\": ", rng.gen_range(0..1000)));
+                generate_value(rng, depth + 1, out);
+            }
+            out.push('}');
+        }
+        (false, 0) => out.push_str(["null", "true", "false"][rng.gen_range(0..3)]),
+        (false, 1) => out.push_str(&rng.gen_range(-1e6..1e6f64).to_string()),
+        (false, _) => {
+            let length = rng.gen_range(0..48);
+            out.push('"');
+            out.extend((0..length).map(|_| rng.gen_range(b'a'..=b'z') as char));
+            out.push_str("\\n\"");
+        }
+    }
+}
+
+/// A top-level array of documents, about a MiB in total.
+fn generate_document() -> String {
+    let mut rng = ChaCha8Rng::seed_from_u64(SEED);
+    let mut document = String::from("[");
+    for i in 0..DOCUMENTS {
+        if i > 0 {
+            document.push(',');
+        }
+        generate_value(&mut rng, 0, &mut document);
+    }
+    document.push(']');
+    document
+}
+
+fn parse<A: Allocator>(allocator: &A, document: &[u8]) {
+    let mut parser = Parser {
+        allocator,
+        input: document,
+        position: 0,
+    };
+    black_box(parser.parse_value());
+}
+
+pub fn bench<A: Allocator>(c: &mut Criterion, allocator_name: &str, make: impl Fn() -> A) {
+    let document = generate_document();
+    let mut group = c.benchmark_group("json_parse");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new(allocator_name, document.len()), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| parse(allocator, document.as_bytes()),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
@@ -0,0 +1,45 @@
+/// Builds a large binary tree of `Box`ed nodes, walks it and drops it.
+use criterion::{black_box, BenchmarkId, Criterion};
+use std::alloc::Allocator;
+
+const DEPTH: u32 = 18;
+
+struct Node<'a, A: Allocator> {
+    value: u64,
+    left: Option<Box<Node<'a, A>, &'a A>>,
+    right: Option<Box<Node<'a, A>, &'a A>>,
+}
+
+fn build<A: Allocator>(allocator: &A, depth: u32, value: u64) -> Box<Node<'_, A>, &A> {
+    let (left, right) = if depth == 0 {
+        (None, None)
+    } else {
+        (
+            Some(build(allocator, depth - 1, 2 * value)),
+            Some(build(allocator, depth - 1, 2 * value + 1)),
+        )
+    };
+    Box::new_in(Node { value, left, right }, allocator)
+}
+
+fn sum<A: Allocator>(node: &Node<'_, A>) -> u64 {
+    node.value + node.left.as_deref().map_or(0, sum) + node.right.as_deref().map_or(0, sum)
+}
+
+fn build_and_drop<A: Allocator>(allocator: &A) {
+    let tree = build(allocator, DEPTH, 1);
+    black_box(sum(&tree));
+}
+
+pub fn bench<A: Allocator>(c: &mut Criterion, allocator_name: &str, make: impl Fn() -> A) {
+    let mut group = c.benchmark_group("tree");
+    group.sample_size(10);
+    group.bench_function(BenchmarkId::new(allocator_name, DEPTH), |b| {
+        b.iter_batched_ref(
+            &make,
+            |allocator| build_and_drop(allocator),
+            criterion::BatchSize::PerIteration,
+        )
+    });
+    group.finish();
+}
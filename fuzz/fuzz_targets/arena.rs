@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{fuzz, ArenaAllocator};
+
+const ARENA_CAPACITY: usize = 64 * 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(|| ArenaAllocator::with_capacity(ARENA_CAPACITY), data);
+});
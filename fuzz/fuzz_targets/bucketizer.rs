@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{
+    fuzz, Bucketizer, GlibcMallocAllocator, MiMallocAllocator, Segregator,
+};
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(
+        || {
+            Segregator::<4095, _, _>::new(
+                Bucketizer::<_, 0, 512, 8>::new(|| MiMallocAllocator),
+                GlibcMallocAllocator,
+            )
+        },
+        data,
+    );
+});
@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{fuzz, CheckedAllocator, GlibcMallocAllocator};
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(|| CheckedAllocator::new(GlibcMallocAllocator), data);
+});
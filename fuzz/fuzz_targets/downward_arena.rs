@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{fuzz, DownwardArenaAllocator};
+
+const ARENA_CAPACITY: usize = 64 * 1024 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(
+        || DownwardArenaAllocator::with_capacity(ARENA_CAPACITY),
+        data,
+    );
+});
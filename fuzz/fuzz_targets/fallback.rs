@@ -0,0 +1,18 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{fuzz, ArenaAllocator, Fallback, GlibcMallocAllocator};
+
+/// Small enough for blocks to move to the secondary allocator.
+const ARENA_CAPACITY: usize = 64 * 1024;
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(
+        || {
+            Fallback::new(
+                ArenaAllocator::with_capacity(ARENA_CAPACITY),
+                GlibcMallocAllocator,
+            )
+        },
+        data,
+    );
+});
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{fuzz, GuardMode, GuardPageAllocator};
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(|| GuardPageAllocator::new(GuardMode::Overflow), data);
+    fuzz::check_bytes(
+        || GuardPageAllocator::new(GuardMode::Underflow).unmap_freed(),
+        data,
+    );
+});
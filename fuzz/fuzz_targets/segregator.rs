@@ -0,0 +1,17 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use memory_allocator_performance_rs::{
+    fuzz, GlibcMallocAllocator, GuardMode, GuardPageAllocator, Segregator, ThreadCache,
+};
+
+fuzz_target!(|data: &[u8]| {
+    fuzz::check_bytes(
+        || {
+            Segregator::<4096, _, _>::new(
+                ThreadCache::new(GlibcMallocAllocator),
+                GuardPageAllocator::new(GuardMode::Overflow).unmap_freed(),
+            )
+        },
+        data,
+    );
+});
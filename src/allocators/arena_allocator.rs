@@ -2,8 +2,23 @@ use std::{
     alloc::{alloc, dealloc, AllocError, Allocator, Layout},
     cell::Cell,
     ptr::NonNull,
+    rc::Rc,
 };
+
+use alloc_fmt::alloc_trace;
+
+use crate::allocators::combinators::Owns;
+
+/// A bump allocator over a single buffer. Clones share the buffer.
+///
+/// Only the most recent block can be taken back when it is freed, which is
+/// enough for stack-like use; the others are reclaimed with the buffer.
+#[derive(Clone)]
 pub struct ArenaAllocator {
+    inner: Rc<Arena>,
+}
+
+struct Arena {
     arena: *mut u8,
     size: usize,
     offset: Cell<usize>,
@@ -18,24 +33,28 @@ impl ArenaAllocator {
             panic!("Failed to allocate memory for arena");
         }
         ArenaAllocator {
-            arena,
-            size,
-            offset: Cell::new(0),
-            layout: Some(layout),
+            inner: Rc::new(Arena {
+                arena,
+                size,
+                offset: Cell::new(0),
+                layout: Some(layout),
+            }),
         }
     }
 
     pub fn from_ptr(ptr: *mut [u8]) -> Self {
         ArenaAllocator {
-            arena: ptr as *mut u8,
-            size: ptr.len(),
-            offset: Cell::new(0),
-            layout: None,
+            inner: Rc::new(Arena {
+                arena: ptr as *mut u8,
+                size: ptr.len(),
+                offset: Cell::new(0),
+                layout: None,
+            }),
         }
     }
 }
 
-impl Drop for ArenaAllocator {
+impl Drop for Arena {
     fn drop(&mut self) {
         if let Some(layout) = self.layout {
             unsafe {
@@ -45,6 +64,14 @@ impl Drop for ArenaAllocator {
     }
 }
 
+impl Owns for ArenaAllocator {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        // The end is included: zero-sized blocks can sit there.
+        let offset = (ptr.as_ptr() as usize).wrapping_sub(self.inner.arena as usize);
+        offset <= self.inner.size
+    }
+}
+
 /// Aligns the given offset to the given alignment.
 fn align_up(offset: usize, align: usize) -> usize {
     (offset + align - 1) & !(align - 1)
@@ -54,39 +81,79 @@ unsafe impl Allocator for ArenaAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         let size = layout.size();
         let align = layout.align();
-        let ptr_offset = align_up(self.offset.get(), align);
+        // Align the address rather than the offset, the buffer itself being byte-aligned.
+        let base = self.inner.arena as usize;
+        let ptr_offset = align_up(base + self.inner.offset.get(), align) - base;
         let new_offset = ptr_offset + size;
-        if new_offset > self.size {
+        if new_offset > self.inner.size {
             return Err(AllocError);
         }
-        let ptr = unsafe { self.arena.add(ptr_offset) };
-        self.offset.set(new_offset);
+        let ptr = unsafe { self.inner.arena.add(ptr_offset) };
+        self.inner.offset.set(new_offset);
         Ok(NonNull::slice_from_raw_parts(
             NonNull::new(ptr).unwrap(),
             size,
         ))
     }
 
-    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
-        println!("Not deallocating memory in arena");
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let ptr_offset = ptr.as_ptr().offset_from(self.inner.arena) as usize;
+        if ptr_offset + layout.size() == self.inner.offset.get() {
+            // The alignment padding below the block is lost.
+            self.inner.offset.set(ptr_offset);
+        } else {
+            alloc_trace!("Not deallocating memory in arena");
+        }
     }
-}
 
-impl Clone for ArenaAllocator {
-    fn clone(&self) -> Self {
-        let layout = self.layout;
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // The last block can grow in place if it is suitably aligned.
+        let ptr_offset = ptr.as_ptr().offset_from(self.inner.arena) as usize;
+        if ptr_offset + old_layout.size() == self.inner.offset.get()
+            && (ptr.as_ptr() as usize).is_multiple_of(new_layout.align())
+        {
+            let new_offset = ptr_offset + new_layout.size();
+            if new_offset > self.inner.size {
+                return Err(AllocError);
+            }
+            self.inner.offset.set(new_offset);
+            return Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()));
+        }
 
-        let arena = if let Some(layout) = layout {
-            unsafe { alloc(layout) }
-        } else {
-            self.arena
-        };
+        let new_ptr = self.allocate(new_layout)?;
+        std::ptr::copy_nonoverlapping(
+            ptr.as_ptr(),
+            new_ptr.cast::<u8>().as_ptr(),
+            old_layout.size(),
+        );
+        Ok(new_ptr)
+    }
 
-        ArenaAllocator {
-            arena,
-            size: self.size,
-            offset: Cell::new(0),
-            layout,
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            let new_ptr = self.allocate(new_layout)?;
+            std::ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new_ptr.cast::<u8>().as_ptr(),
+                new_layout.size(),
+            );
+            return Ok(new_ptr);
+        }
+        // Give the tail back if this is the last block.
+        let ptr_offset = ptr.as_ptr().offset_from(self.inner.arena) as usize;
+        if ptr_offset + old_layout.size() == self.inner.offset.get() {
+            self.inner.offset.set(ptr_offset + new_layout.size());
         }
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
     }
 }
@@ -0,0 +1,235 @@
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use alloc_fmt::alloc_panic;
+
+/// Bytes of canary on each side of a block.
+const CANARY_SIZE: usize = 16;
+const CANARY: u8 = 0xca;
+/// Written over freed blocks, so that use-after-free writes can be detected.
+const POISON: u8 = 0xdd;
+/// Number of freed blocks held back before they are returned to the inner allocator.
+const QUARANTINE_LEN: usize = 256;
+
+const ALLOCATED: usize = 0xa110_ca7e;
+const FREED: usize = 0xf7ee_d0ff;
+
+/// Stored in front of the leading canary of every block.
+#[repr(C)]
+struct Header {
+    state: usize,
+    size: usize,
+    align: usize,
+}
+
+const HEADER_SIZE: usize = std::mem::size_of::<Header>();
+
+/// A freed block waiting in quarantine, with the layout it was allocated with.
+#[derive(Clone, Copy)]
+struct Quarantined {
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+struct Quarantine {
+    blocks: [Option<Quarantined>; QUARANTINE_LEN],
+    next: usize,
+}
+
+unsafe impl Send for Quarantine {}
+
+/// A debugging wrapper catching misuse of the inner allocator.
+///
+/// Every block is surrounded by canaries, which are checked when it is freed
+/// to catch buffer overflows and underflows. Freed blocks are poisoned and
+/// kept in a quarantine for a while before being really freed; the poison is
+/// checked on the way out to catch writes after free. Freeing a block with
+/// another layout than it was allocated with, or freeing it twice while it is
+/// in quarantine, is reported as well.
+///
+/// Double frees are found by looking the block up in the quarantine. Once a
+/// block has left it, its memory belongs to the inner allocator again: freeing
+/// it a second time reads a header that is no longer ours, which is a use after
+/// free. It is still reported if the memory was not reused, but goes unnoticed
+/// if it now holds a new block of the same layout.
+///
+/// Violations abort the process through `alloc_panic!`, which makes this
+/// wrapper usable as a `GlobalAlloc`. Through the `Allocator` interface, they
+/// unwind instead once `alloc_fmt::set_catchable_panics` is enabled, so that
+/// tests can expect them.
+pub struct CheckedAllocator<A: Allocator> {
+    inner: A,
+    quarantine: spin::Mutex<Quarantine>,
+}
+
+impl<A: Allocator> CheckedAllocator<A> {
+    pub const fn new(inner: A) -> Self {
+        CheckedAllocator {
+            inner,
+            quarantine: spin::Mutex::new(Quarantine {
+                blocks: [None; QUARANTINE_LEN],
+                next: 0,
+            }),
+        }
+    }
+
+    /// Layout of the block requested from the inner allocator, and offset of the
+    /// user pointer in it.
+    fn inner_layout(layout: Layout) -> Result<(Layout, usize), AllocError> {
+        let offset = (HEADER_SIZE + CANARY_SIZE).next_multiple_of(layout.align());
+        let size = offset
+            .checked_add(layout.size())
+            .and_then(|size| size.checked_add(CANARY_SIZE))
+            .ok_or(AllocError)?;
+        let align = layout.align().max(std::mem::align_of::<Header>());
+        let layout = Layout::from_size_align(size, align).map_err(|_| AllocError)?;
+        Ok((layout, offset))
+    }
+
+    unsafe fn header<'a>(ptr: NonNull<u8>) -> &'a mut Header {
+        &mut *ptr.as_ptr().sub(CANARY_SIZE + HEADER_SIZE).cast::<Header>()
+    }
+
+    fn allocate_checked(&self, layout: Layout, zeroed: bool) -> Result<NonNull<[u8]>, AllocError> {
+        let (inner_layout, offset) = Self::inner_layout(layout)?;
+        let block = self.inner.allocate(inner_layout)?.cast::<u8>();
+        unsafe {
+            let ptr = block.add(offset);
+            ptr.sub(CANARY_SIZE).write_bytes(CANARY, CANARY_SIZE);
+            ptr.add(layout.size()).write_bytes(CANARY, CANARY_SIZE);
+            if zeroed {
+                ptr.write_bytes(0, layout.size());
+            }
+            *Self::header(ptr) = Header {
+                state: ALLOCATED,
+                size: layout.size(),
+                align: layout.align(),
+            };
+            Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+        }
+    }
+
+    /// Checks a block being freed and returns it to the inner allocator, through
+    /// the quarantine.
+    unsafe fn deallocate_checked(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.is_quarantined(ptr) {
+            alloc_panic!("CheckedAllocator: double free of {:p}", ptr);
+        }
+        let header = Self::header(ptr);
+        match header.state {
+            ALLOCATED => {}
+            FREED => alloc_panic!(
+                "CheckedAllocator: double free of {:p}, already released from quarantine",
+                ptr
+            ),
+            _ => alloc_panic!(
+                "CheckedAllocator: freeing {:p}, which was not allocated by this allocator or whose header was overwritten",
+                ptr
+            ),
+        }
+        if header.size != layout.size() || header.align != layout.align() {
+            alloc_panic!(
+                "CheckedAllocator: {:p} allocated with size {} and align {}, but freed with {:?}",
+                ptr,
+                header.size,
+                header.align,
+                layout
+            );
+        }
+        if !is_filled(ptr.sub(CANARY_SIZE), CANARY_SIZE, CANARY) {
+            alloc_panic!("CheckedAllocator: buffer underflow before {:p}", ptr);
+        }
+        if !is_filled(ptr.add(layout.size()), CANARY_SIZE, CANARY) {
+            alloc_panic!(
+                "CheckedAllocator: buffer overflow after {:p} ({} bytes)",
+                ptr,
+                layout.size()
+            );
+        }
+
+        header.state = FREED;
+        ptr.write_bytes(POISON, layout.size());
+        let evicted = {
+            let mut quarantine = self.quarantine.lock();
+            let next = quarantine.next;
+            quarantine.next = (next + 1) % QUARANTINE_LEN;
+            quarantine.blocks[next].replace(Quarantined { ptr, layout })
+        };
+        if let Some(block) = evicted {
+            self.release(block);
+        }
+    }
+
+    fn is_quarantined(&self, ptr: NonNull<u8>) -> bool {
+        let quarantine = self.quarantine.lock();
+        quarantine
+            .blocks
+            .iter()
+            .flatten()
+            .any(|block| block.ptr == ptr)
+    }
+
+    /// Checks that a quarantined block was not written to, and frees it for real.
+    unsafe fn release(&self, block: Quarantined) {
+        if !is_filled(block.ptr, block.layout.size(), POISON) {
+            alloc_panic!(
+                "CheckedAllocator: {:p} ({} bytes) written to after being freed",
+                block.ptr,
+                block.layout.size()
+            );
+        }
+        // `inner_layout` cannot fail here since it succeeded when allocating.
+        let (inner_layout, offset) = Self::inner_layout(block.layout).unwrap_unchecked();
+        self.inner.deallocate(block.ptr.sub(offset), inner_layout);
+    }
+}
+
+unsafe fn is_filled(ptr: NonNull<u8>, size: usize, byte: u8) -> bool {
+    std::slice::from_raw_parts(ptr.as_ptr(), size)
+        .iter()
+        .all(|&b| b == byte)
+}
+
+impl<A: Allocator> Drop for CheckedAllocator<A> {
+    fn drop(&mut self) {
+        let blocks = self.quarantine.get_mut().blocks;
+        for block in blocks.into_iter().flatten() {
+            unsafe { self.release(block) };
+        }
+    }
+}
+
+unsafe impl<A: Allocator> Allocator for CheckedAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_checked(layout, false)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_checked(layout, true)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.deallocate_checked(ptr, layout)
+    }
+}
+
+unsafe impl<A: Allocator> GlobalAlloc for CheckedAllocator<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _allocator = alloc_fmt::enter_allocator();
+        self.allocate_checked(layout, false)
+            .map_or(null_mut(), |ptr| ptr.cast().as_ptr())
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let _allocator = alloc_fmt::enter_allocator();
+        self.allocate_checked(layout, true)
+            .map_or(null_mut(), |ptr| ptr.cast().as_ptr())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _allocator = alloc_fmt::enter_allocator();
+        self.deallocate_checked(NonNull::new_unchecked(ptr), layout)
+    }
+}
@@ -0,0 +1,418 @@
+//! Allocators built out of other allocators, after Andrei Alexandrescu's
+//! composable allocators: `Fallback` tries one allocator and then another,
+//! `Segregator` picks one by block size, and `Bucketizer` keeps one per range of
+//! sizes.
+use alloc_fmt::{alloc_assert, alloc_panic};
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+};
+
+/// Allocators that can tell whether they allocated a block.
+///
+/// `GlibcMallocAllocator` and `JemallocAllocator` do not implement it: neither
+/// library can check a pointer it did not allocate. `malloc_usable_size`,
+/// `sallocx` and jemalloc's `arenas.lookup` all read the metadata of the block
+/// they are given, and crash or return garbage for foreign pointers.
+pub trait Owns {
+    /// Whether `ptr` points to a block allocated by this allocator, or one of
+    /// its clones. Only meaningful for live blocks.
+    fn owns(&self, ptr: NonNull<u8>) -> bool;
+}
+
+/// Moves a block from one allocator to another, for resizes that cannot stay
+/// with the same allocator.
+unsafe fn move_block(
+    from: &impl Allocator,
+    to: &impl Allocator,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    let new_ptr = if zeroed {
+        to.allocate_zeroed(new_layout)?
+    } else {
+        to.allocate(new_layout)?
+    };
+    std::ptr::copy_nonoverlapping(
+        ptr.as_ptr(),
+        new_ptr.cast::<u8>().as_ptr(),
+        old_layout.size().min(new_layout.size()),
+    );
+    from.deallocate(ptr, old_layout);
+    Ok(new_ptr)
+}
+
+/// Shortens `block` to at most `max` bytes. A block may be freed with any size
+/// up to the length returned, so that length must not reach the sizes served by
+/// another allocator.
+fn truncate(block: NonNull<[u8]>, max: usize) -> NonNull<[u8]> {
+    NonNull::slice_from_raw_parts(block.cast(), block.len().min(max))
+}
+
+/// Grows or shrinks a block with the allocator that owns it. `zeroed` is only
+/// set when growing.
+unsafe fn resize_with(
+    allocator: &impl Allocator,
+    ptr: NonNull<u8>,
+    old_layout: Layout,
+    new_layout: Layout,
+    zeroed: bool,
+) -> Result<NonNull<[u8]>, AllocError> {
+    if new_layout.size() < old_layout.size() {
+        allocator.shrink(ptr, old_layout, new_layout)
+    } else if zeroed {
+        allocator.grow_zeroed(ptr, old_layout, new_layout)
+    } else {
+        allocator.grow(ptr, old_layout, new_layout)
+    }
+}
+
+/// Allocates from `Primary`, and from `Secondary` when `Primary` fails, for
+/// instance an arena backed by a general-purpose allocator once it is
+/// exhausted.
+///
+/// Blocks are freed and resized by the allocator that owns them, which is why
+/// `Primary` has to implement `Owns`. A block of `Primary` that cannot grow in
+/// place moves to `Secondary`.
+#[derive(Clone, Default)]
+pub struct Fallback<Primary, Secondary> {
+    primary: Primary,
+    secondary: Secondary,
+}
+
+impl<Primary, Secondary> Fallback<Primary, Secondary> {
+    pub const fn new(primary: Primary, secondary: Secondary) -> Self {
+        Fallback { primary, secondary }
+    }
+}
+
+impl<Primary: Allocator + Owns, Secondary: Allocator> Fallback<Primary, Secondary> {
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !self.primary.owns(ptr) {
+            return resize_with(&self.secondary, ptr, old_layout, new_layout, zeroed);
+        }
+        resize_with(&self.primary, ptr, old_layout, new_layout, zeroed).or_else(|_| {
+            move_block(
+                &self.primary,
+                &self.secondary,
+                ptr,
+                old_layout,
+                new_layout,
+                zeroed,
+            )
+        })
+    }
+}
+
+unsafe impl<Primary: Allocator + Owns, Secondary: Allocator> Allocator
+    for Fallback<Primary, Secondary>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.primary
+            .allocate(layout)
+            .or_else(|_| self.secondary.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.primary
+            .allocate_zeroed(layout)
+            .or_else(|_| self.secondary.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if self.primary.owns(ptr) {
+            self.primary.deallocate(ptr, layout)
+        } else {
+            self.secondary.deallocate(ptr, layout)
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+}
+
+impl<Primary: Owns, Secondary: Owns> Owns for Fallback<Primary, Secondary> {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.primary.owns(ptr) || self.secondary.owns(ptr)
+    }
+}
+
+/// Allocates blocks of up to `THRESHOLD` bytes from `Small`, and larger ones
+/// from `Large`, for instance a slab allocator for small blocks and `mmap` for
+/// the others. In this crate, the size-class magazines of `ThreadCache` and a
+/// `GuardPageAllocator` unmapping freed blocks come closest to those.
+///
+/// The size of a block tells which allocator it belongs to, so no ownership
+/// check is needed: blocks of `Small` are reported as at most `THRESHOLD` bytes
+/// long, even when `Small` rounds sizes up. Resizing a block across the
+/// threshold moves it.
+#[derive(Clone, Default)]
+pub struct Segregator<const THRESHOLD: usize, Small, Large> {
+    small: Small,
+    large: Large,
+}
+
+impl<const THRESHOLD: usize, Small, Large> Segregator<THRESHOLD, Small, Large> {
+    pub const fn new(small: Small, large: Large) -> Self {
+        Segregator { small, large }
+    }
+}
+
+impl<const THRESHOLD: usize, Small: Allocator, Large: Allocator>
+    Segregator<THRESHOLD, Small, Large>
+{
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        match (
+            old_layout.size() <= THRESHOLD,
+            new_layout.size() <= THRESHOLD,
+        ) {
+            (true, true) => resize_with(&self.small, ptr, old_layout, new_layout, zeroed)
+                .map(|block| truncate(block, THRESHOLD)),
+            (false, false) => resize_with(&self.large, ptr, old_layout, new_layout, zeroed),
+            (true, false) => move_block(
+                &self.small,
+                &self.large,
+                ptr,
+                old_layout,
+                new_layout,
+                zeroed,
+            ),
+            (false, true) => {
+                move_block(&self.large, &self.small, ptr, old_layout, new_layout, false)
+                    .map(|block| truncate(block, THRESHOLD))
+            }
+        }
+    }
+}
+
+unsafe impl<const THRESHOLD: usize, Small: Allocator, Large: Allocator> Allocator
+    for Segregator<THRESHOLD, Small, Large>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() <= THRESHOLD {
+            self.small
+                .allocate(layout)
+                .map(|block| truncate(block, THRESHOLD))
+        } else {
+            self.large.allocate(layout)
+        }
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() <= THRESHOLD {
+            self.small
+                .allocate_zeroed(layout)
+                .map(|block| truncate(block, THRESHOLD))
+        } else {
+            self.large.allocate_zeroed(layout)
+        }
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() <= THRESHOLD {
+            self.small.deallocate(ptr, layout)
+        } else {
+            self.large.deallocate(ptr, layout)
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+}
+
+impl<const THRESHOLD: usize, Small: Owns, Large: Owns> Owns
+    for Segregator<THRESHOLD, Small, Large>
+{
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.small.owns(ptr) || self.large.owns(ptr)
+    }
+}
+
+/// `BUCKETS` allocators, the `i`-th serving the sizes from `MIN + i * STEP`
+/// to `MIN + (i + 1) * STEP - 1` bytes, so that blocks of similar sizes end up
+/// together.
+///
+/// Other sizes are refused: combine with `Segregator` to serve them from
+/// another allocator. Blocks are reported as at most as long as the largest size
+/// of their bucket, and resizing a block to another bucket moves it.
+#[derive(Clone)]
+pub struct Bucketizer<A, const MIN: usize, const STEP: usize, const BUCKETS: usize> {
+    buckets: [A; BUCKETS],
+}
+
+impl<A, const MIN: usize, const STEP: usize, const BUCKETS: usize>
+    Bucketizer<A, MIN, STEP, BUCKETS>
+{
+    /// Largest size served.
+    pub const MAX: usize = MIN + STEP * BUCKETS - 1;
+
+    /// Creates the allocator of each bucket with `make`.
+    pub fn new(mut make: impl FnMut() -> A) -> Self {
+        assert!(STEP > 0, "Bucketizer: STEP must not be zero");
+        Bucketizer {
+            buckets: std::array::from_fn(|_| make()),
+        }
+    }
+
+    /// Returns the bucket serving `size`, with the largest size it serves.
+    fn bucket(&self, size: usize) -> Option<(&A, usize)> {
+        let index = size.checked_sub(MIN)? / STEP;
+        let bucket = self.buckets.get(index)?;
+        Some((bucket, MIN + (index + 1) * STEP - 1))
+    }
+}
+
+impl<A: Default, const MIN: usize, const STEP: usize, const BUCKETS: usize> Default
+    for Bucketizer<A, MIN, STEP, BUCKETS>
+{
+    fn default() -> Self {
+        Self::new(A::default)
+    }
+}
+
+impl<A: Allocator, const MIN: usize, const STEP: usize, const BUCKETS: usize>
+    Bucketizer<A, MIN, STEP, BUCKETS>
+{
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        zeroed: bool,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        // The block was allocated here, so it has a bucket.
+        let (old, _) = self.bucket(old_layout.size()).ok_or(AllocError)?;
+        let (new, max) = self.bucket(new_layout.size()).ok_or(AllocError)?;
+        if std::ptr::eq(old, new) {
+            resize_with(old, ptr, old_layout, new_layout, zeroed)
+        } else {
+            move_block(old, new, ptr, old_layout, new_layout, zeroed)
+        }
+        .map(|block| truncate(block, max))
+    }
+}
+
+unsafe impl<A: Allocator, const MIN: usize, const STEP: usize, const BUCKETS: usize> Allocator
+    for Bucketizer<A, MIN, STEP, BUCKETS>
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (bucket, max) = self.bucket(layout.size()).ok_or(AllocError)?;
+        bucket.allocate(layout).map(|block| truncate(block, max))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let (bucket, max) = self.bucket(layout.size()).ok_or(AllocError)?;
+        bucket
+            .allocate_zeroed(layout)
+            .map(|block| truncate(block, max))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let bucket = self.bucket(layout.size());
+        alloc_assert!(
+            bucket.is_some(),
+            "Bucketizer: no bucket for a block of {} bytes",
+            layout.size()
+        );
+        if let Some((bucket, _)) = bucket {
+            bucket.deallocate(ptr, layout)
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, true)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout, false)
+    }
+}
+
+impl<A: Owns, const MIN: usize, const STEP: usize, const BUCKETS: usize> Owns
+    for Bucketizer<A, MIN, STEP, BUCKETS>
+{
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        self.buckets.iter().any(|bucket| bucket.owns(ptr))
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    alloc::{alloc, dealloc, AllocError, Allocator, Layout},
+    cell::Cell,
+    ptr::NonNull,
+    rc::Rc,
+};
+
+use alloc_fmt::alloc_trace;
+
+use crate::allocators::combinators::Owns;
+
+/// A bump allocator over a single buffer, allocating from its end downward.
+/// Clones share the buffer.
+///
+/// Bumping downward rounds the new address down to the alignment, which is a
+/// single mask, where bumping upward has to round up and then check the end of
+/// the block against the end of the buffer. The whole fast path is a checked
+/// subtraction, a mask and one comparison. Freeing the last block gives its
+/// memory back; other blocks are only reclaimed with the buffer.
+#[derive(Clone)]
+pub struct DownwardArenaAllocator {
+    inner: Rc<Arena>,
+}
+
+struct Arena {
+    arena: *mut u8,
+    /// Lowest address of the buffer.
+    start: usize,
+    /// Address just past the buffer.
+    end: usize,
+    /// Address of the last block allocated, the end of the buffer at first.
+    ptr: Cell<usize>,
+    layout: Option<Layout>,
+}
+
+impl DownwardArenaAllocator {
+    pub fn with_capacity(size: usize) -> Self {
+        let layout = Layout::from_size_align(size, 1).unwrap();
+        let arena = unsafe { alloc(layout) };
+        if arena.is_null() {
+            panic!("Failed to allocate memory for arena");
+        }
+        Self::new(arena, size, Some(layout))
+    }
+
+    pub fn from_ptr(ptr: *mut [u8]) -> Self {
+        Self::new(ptr as *mut u8, ptr.len(), None)
+    }
+
+    fn new(arena: *mut u8, size: usize, layout: Option<Layout>) -> Self {
+        DownwardArenaAllocator {
+            inner: Rc::new(Arena {
+                arena,
+                start: arena as usize,
+                end: arena as usize + size,
+                ptr: Cell::new(arena as usize + size),
+                layout,
+            }),
+        }
+    }
+}
+
+impl Drop for Arena {
+    fn drop(&mut self) {
+        if let Some(layout) = self.layout {
+            unsafe {
+                dealloc(self.arena, layout);
+            }
+        }
+    }
+}
+
+impl Owns for DownwardArenaAllocator {
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        // The end is included: zero-sized blocks can sit there.
+        (self.inner.start..=self.inner.end).contains(&(ptr.as_ptr() as usize))
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn out_of_memory(layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+    alloc_trace!("arena exhausted, cannot allocate {:?}", layout);
+    Err(AllocError)
+}
+
+unsafe impl Allocator for DownwardArenaAllocator {
+    #[inline(always)]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let arena = &*self.inner;
+        let new_ptr = match arena.ptr.get().checked_sub(layout.size()) {
+            Some(ptr) => ptr & !(layout.align() - 1),
+            None => return out_of_memory(layout),
+        };
+        if new_ptr < arena.start {
+            return out_of_memory(layout);
+        }
+        arena.ptr.set(new_ptr);
+        // Keep the provenance of the buffer.
+        let ptr = unsafe { arena.arena.add(new_ptr - arena.start) };
+        Ok(NonNull::slice_from_raw_parts(
+            unsafe { NonNull::new_unchecked(ptr) },
+            layout.size(),
+        ))
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let arena = &*self.inner;
+        if ptr.as_ptr() as usize == arena.ptr.get() {
+            // The alignment padding above the block is lost.
+            arena.ptr.set(arena.ptr.get() + layout.size());
+        } else {
+            alloc_trace!("Not deallocating memory in arena");
+        }
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if !(ptr.as_ptr() as usize).is_multiple_of(new_layout.align()) {
+            let new_ptr = self.allocate(new_layout)?;
+            std::ptr::copy_nonoverlapping(
+                ptr.as_ptr(),
+                new_ptr.cast::<u8>().as_ptr(),
+                new_layout.size(),
+            );
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+        // Blocks grow upward from their address, so the tail cannot be given
+        // back, even for the last block.
+        Ok(NonNull::slice_from_raw_parts(ptr, new_layout.size()))
+    }
+}
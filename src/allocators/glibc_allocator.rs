@@ -3,7 +3,20 @@ use std::{
     ptr::NonNull,
 };
 
-use libc::{free, malloc, realloc};
+use libc::{c_void, calloc, free, malloc, posix_memalign, realloc};
+
+/// Alignment guaranteed by `malloc`, `calloc` and `realloc`. Larger alignments
+/// go through `posix_memalign`.
+pub(crate) const MIN_ALIGN: usize = 2 * std::mem::size_of::<usize>();
+
+/// Allocates with `posix_memalign`, returning null on failure like `malloc`.
+pub(crate) unsafe fn aligned_malloc(layout: Layout) -> *mut u8 {
+    let mut ptr = std::ptr::null_mut();
+    if posix_memalign(&mut ptr, layout.align(), layout.size()) != 0 {
+        return std::ptr::null_mut();
+    }
+    ptr.cast()
+}
 
 #[derive(Clone)]
 pub struct GlibcMallocAllocator;
@@ -11,52 +24,86 @@ pub struct GlibcMallocAllocator;
 unsafe impl Allocator for GlibcMallocAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         let size = layout.size();
-        let ptr = unsafe { malloc(size) };
+        let ptr = if layout.align() <= MIN_ALIGN {
+            unsafe { malloc(size) as *mut u8 }
+        } else {
+            unsafe { aligned_malloc(layout) }
+        };
         if ptr.is_null() {
             Err(AllocError)
         } else {
             Ok(NonNull::slice_from_raw_parts(
-                NonNull::new(ptr as *mut u8).unwrap(),
+                NonNull::new(ptr).unwrap(),
                 layout.size(),
             ))
         }
     }
 
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.align() > MIN_ALIGN {
+            let ptr = self.allocate(layout)?;
+            unsafe { ptr.cast::<u8>().write_bytes(0, layout.size()) };
+            return Ok(ptr);
+        }
+        let size = layout.size();
+        let ptr = unsafe { calloc(1, size) };
+        if ptr.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr as *mut u8).unwrap(),
+                size,
+            ))
+        }
+    }
+
     unsafe fn deallocate(&self, ptr: NonNull<u8>, _layout: Layout) {
-        free(ptr.as_ptr() as *mut std::ffi::c_void);
+        free(ptr.as_ptr() as *mut c_void);
     }
 
     unsafe fn grow(
         &self,
         ptr: NonNull<u8>,
-        _old_layout: Layout,
+        old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
-        let new_size = new_layout.size();
-        let new_ptr = realloc(ptr.as_ptr() as *mut std::ffi::c_void, new_size);
-        if new_ptr.is_null() {
-            Err(AllocError)
-        } else {
-            Ok(NonNull::slice_from_raw_parts(
-                NonNull::new(new_ptr as *mut u8).unwrap(),
-                new_size,
-            ))
-        }
+        self.resize(ptr, old_layout, new_layout)
     }
 
     unsafe fn shrink(
         &self,
         ptr: NonNull<u8>,
-        _old_layout: Layout,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl GlibcMallocAllocator {
+    /// Resizes with `realloc`, which only guarantees `MIN_ALIGN` and frees the
+    /// block when shrinking it to zero bytes.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
         new_layout: Layout,
     ) -> Result<NonNull<[u8]>, AllocError> {
         let new_size = new_layout.size();
-        let new_ptr = realloc(ptr.as_ptr() as *mut std::ffi::c_void, new_size);
+        let new_ptr = if new_layout.align() <= MIN_ALIGN && new_size != 0 {
+            realloc(ptr.as_ptr() as *mut c_void, new_size) as *mut u8
+        } else {
+            let new_ptr = self.allocate(new_layout)?.cast::<u8>().as_ptr();
+            let size = old_layout.size().min(new_size);
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr, size);
+            self.deallocate(ptr, old_layout);
+            new_ptr
+        };
         if new_ptr.is_null() {
             Err(AllocError)
         } else {
             Ok(NonNull::slice_from_raw_parts(
-                NonNull::new(new_ptr as *mut u8).unwrap(),
+                NonNull::new(new_ptr).unwrap(),
                 new_size,
             ))
         }
@@ -0,0 +1,158 @@
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    ptr::{null_mut, NonNull},
+};
+
+use libc::{
+    c_void, mmap, mprotect, munmap, sysconf, _SC_PAGESIZE, MAP_ANONYMOUS, MAP_FAILED, MAP_FIXED,
+    MAP_NORESERVE, MAP_PRIVATE, PROT_NONE, PROT_READ, PROT_WRITE,
+};
+
+/// Which side of the blocks the guard page protects.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GuardMode {
+    /// Blocks end right before the guard page, trapping accesses past their end.
+    /// Up to `align - 1` bytes of padding after a block go unnoticed.
+    Overflow,
+    /// Blocks start right after the guard page, trapping accesses before their start.
+    Underflow,
+}
+
+/// An "electric fence" allocator for hunting memory bugs: every block gets its
+/// own mapping next to an inaccessible guard page, so that the faulty access
+/// itself crashes with `SIGSEGV`.
+///
+/// Freed blocks are made inaccessible too, trapping use after free, but stay
+/// reserved so that their addresses are never reused. `unmap_freed` returns
+/// them to the system instead.
+///
+/// Every block costs at least two pages, and alignments above the page size are
+/// not supported.
+#[derive(Clone, Copy, Debug)]
+pub struct GuardPageAllocator {
+    mode: GuardMode,
+    unmap_freed: bool,
+}
+
+fn page_size() -> usize {
+    unsafe { sysconf(_SC_PAGESIZE) as usize }
+}
+
+impl GuardPageAllocator {
+    pub const fn new(mode: GuardMode) -> Self {
+        GuardPageAllocator {
+            mode,
+            unmap_freed: false,
+        }
+    }
+
+    /// Unmaps freed blocks, at the cost of missing uses after free once their
+    /// addresses get reused.
+    pub const fn unmap_freed(self) -> Self {
+        GuardPageAllocator {
+            unmap_freed: true,
+            ..self
+        }
+    }
+
+    /// Size of the mapping for `layout`, guard page included.
+    fn mapping_size(layout: Layout, page_size: usize) -> Option<usize> {
+        let data_pages = layout.size().div_ceil(page_size).max(1);
+        data_pages.checked_add(1)?.checked_mul(page_size)
+    }
+
+    fn map(&self, layout: Layout) -> *mut u8 {
+        let page_size = page_size();
+        if layout.align() > page_size {
+            return null_mut();
+        }
+        let Some(size) = Self::mapping_size(layout, page_size) else {
+            return null_mut();
+        };
+        unsafe {
+            let mapping = mmap(
+                null_mut(),
+                size,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if mapping == MAP_FAILED {
+                return null_mut();
+            }
+            let mapping = mapping.cast::<u8>();
+            let guard = match self.mode {
+                GuardMode::Overflow => mapping.add(size - page_size),
+                GuardMode::Underflow => mapping,
+            };
+            if mprotect(guard.cast(), page_size, PROT_NONE) != 0 {
+                munmap(mapping.cast(), size);
+                return null_mut();
+            }
+            match self.mode {
+                GuardMode::Overflow => {
+                    let end = guard as usize;
+                    let start = (end - layout.size()) & !(layout.align() - 1);
+                    mapping.add(start - mapping as usize)
+                }
+                GuardMode::Underflow => mapping.add(page_size),
+            }
+        }
+    }
+
+    unsafe fn unmap(&self, ptr: *mut u8, layout: Layout) {
+        let page_size = page_size();
+        let size = Self::mapping_size(layout, page_size).unwrap_unchecked();
+        let mapping = match self.mode {
+            GuardMode::Overflow => {
+                let guard = (ptr as usize + layout.size()).next_multiple_of(page_size);
+                ptr.sub(ptr as usize - (guard + page_size - size))
+            }
+            GuardMode::Underflow => ptr.sub(page_size),
+        };
+        if self.unmap_freed {
+            munmap(mapping.cast(), size);
+        } else {
+            // Replacing the pages releases their memory while keeping them reserved.
+            mmap(
+                mapping.cast::<c_void>(),
+                size,
+                PROT_NONE,
+                MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED | MAP_NORESERVE,
+                -1,
+                0,
+            );
+        }
+    }
+}
+
+unsafe impl Allocator for GuardPageAllocator {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(self.map(layout)).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        // Fresh anonymous mappings are zeroed already.
+        self.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.unmap(ptr.as_ptr(), layout)
+    }
+}
+
+unsafe impl GlobalAlloc for GuardPageAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.map(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.map(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.unmap(ptr, layout)
+    }
+}
@@ -10,8 +10,17 @@ pub struct JemallocAllocator {
     inner: Jemalloc,
 }
 
+/// jemalloc does not accept zero-sized requests, which get a dangling pointer instead.
+fn dangling(layout: Layout) -> NonNull<[u8]> {
+    let ptr = std::ptr::without_provenance_mut(layout.align());
+    NonNull::slice_from_raw_parts(NonNull::new(ptr).unwrap(), 0)
+}
+
 unsafe impl Allocator for JemallocAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout));
+        }
         let ptr = unsafe { self.inner.alloc(layout) };
         if ptr.is_null() {
             Err(AllocError)
@@ -23,7 +32,76 @@ unsafe impl Allocator for JemallocAllocator {
         }
     }
 
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        if layout.size() == 0 {
+            return Ok(dangling(layout));
+        }
+        let ptr = unsafe { self.inner.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr).unwrap(),
+                layout.size(),
+            ))
+        }
+    }
+
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        if layout.size() == 0 {
+            return;
+        }
         self.inner.dealloc(ptr.as_ptr(), layout);
     }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl JemallocAllocator {
+    /// Resizes with `realloc`, which can only keep the original alignment and
+    /// needs both sizes to be non-zero.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = if old_layout.align() == new_layout.align()
+            && old_layout.size() != 0
+            && new_layout.size() != 0
+        {
+            self.inner
+                .realloc(ptr.as_ptr(), old_layout, new_layout.size())
+        } else {
+            let new_ptr = self.allocate(new_layout)?.cast::<u8>().as_ptr();
+            let size = old_layout.size().min(new_layout.size());
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr, size);
+            self.deallocate(ptr, old_layout);
+            new_ptr
+        };
+        if new_ptr.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(new_ptr).unwrap(),
+                new_layout.size(),
+            ))
+        }
+    }
 }
@@ -0,0 +1,123 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    ptr::NonNull,
+    sync::Arc,
+};
+
+use crate::Histogram;
+
+/// Records the latency of every `allocate`, `deallocate` and resize (`grow`,
+/// `grow_zeroed` and `shrink`) call of the inner allocator, in nanoseconds.
+///
+/// Clones share the same histograms, so an allocator cloned across threads
+/// reports the latencies of all of them, at the cost of contention on the
+/// histograms. To avoid it, give each thread a `fork` and `merge` it back once
+/// the thread is done.
+#[derive(Clone)]
+pub struct LatencyAllocator<A: Allocator> {
+    inner: A,
+    allocations: Arc<Histogram>,
+    deallocations: Arc<Histogram>,
+    resizes: Arc<Histogram>,
+}
+
+impl<A: Allocator> LatencyAllocator<A> {
+    pub fn new(inner: A) -> Self {
+        LatencyAllocator {
+            inner,
+            allocations: Arc::new(Histogram::new()),
+            deallocations: Arc::new(Histogram::new()),
+            resizes: Arc::new(Histogram::new()),
+        }
+    }
+
+    /// Returns an allocator wrapping a clone of the inner allocator, with
+    /// histograms of its own.
+    pub fn fork(&self) -> Self
+    where
+        A: Clone,
+    {
+        Self::new(self.inner.clone())
+    }
+
+    /// Adds the latencies recorded by `other` to the histograms of this allocator.
+    pub fn merge(&self, other: &Self) {
+        self.allocations.merge(&other.allocations);
+        self.deallocations.merge(&other.deallocations);
+        self.resizes.merge(&other.resizes);
+    }
+
+    pub fn allocations(&self) -> &Histogram {
+        &self.allocations
+    }
+
+    pub fn deallocations(&self) -> &Histogram {
+        &self.deallocations
+    }
+
+    pub fn resizes(&self) -> &Histogram {
+        &self.resizes
+    }
+}
+
+/// Reads `CLOCK_MONOTONIC_RAW`, which is not subject to NTP adjustments.
+#[inline(always)]
+fn now_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC_RAW, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000_000 + ts.tv_nsec as u64
+}
+
+unsafe impl<A: Allocator> Allocator for LatencyAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let start = now_ns();
+        let result = self.inner.allocate(layout);
+        self.allocations.record(now_ns() - start);
+        result
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let start = now_ns();
+        self.inner.deallocate(ptr, layout);
+        self.deallocations.record(now_ns() - start);
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let start = now_ns();
+        let result = self.inner.grow(ptr, old_layout, new_layout);
+        self.resizes.record(now_ns() - start);
+        result
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let start = now_ns();
+        let result = self.inner.grow_zeroed(ptr, old_layout, new_layout);
+        self.resizes.record(now_ns() - start);
+        result
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let start = now_ns();
+        let result = self.inner.shrink(ptr, old_layout, new_layout);
+        self.resizes.record(now_ns() - start);
+        result
+    }
+}
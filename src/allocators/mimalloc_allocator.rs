@@ -5,6 +5,8 @@ use std::{
 
 use mimalloc::MiMalloc;
 
+use crate::allocators::combinators::Owns;
+
 #[derive(Clone)]
 pub struct MiMallocAllocator;
 
@@ -21,7 +23,73 @@ unsafe impl Allocator for MiMallocAllocator {
         }
     }
 
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = unsafe { MiMalloc.alloc_zeroed(layout) };
+        if ptr.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(ptr).unwrap(),
+                layout.size(),
+            ))
+        }
+    }
+
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
         MiMalloc.dealloc(ptr.as_ptr(), layout);
     }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl Owns for MiMallocAllocator {
+    /// Whether `ptr` is in mimalloc's heap, which blocks from any other user of
+    /// mimalloc in the process are too.
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        unsafe { libmimalloc_sys::mi_is_in_heap_region(ptr.as_ptr().cast()) }
+    }
+}
+
+impl MiMallocAllocator {
+    /// Resizes with `realloc`, which can only keep the original alignment.
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let new_ptr = if old_layout.align() == new_layout.align() {
+            MiMalloc.realloc(ptr.as_ptr(), old_layout, new_layout.size())
+        } else {
+            let new_ptr = self.allocate(new_layout)?.cast::<u8>().as_ptr();
+            let size = old_layout.size().min(new_layout.size());
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr, size);
+            self.deallocate(ptr, old_layout);
+            new_ptr
+        };
+        if new_ptr.is_null() {
+            Err(AllocError)
+        } else {
+            Ok(NonNull::slice_from_raw_parts(
+                NonNull::new(new_ptr).unwrap(),
+                new_layout.size(),
+            ))
+        }
+    }
 }
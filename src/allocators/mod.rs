@@ -1,6 +1,13 @@
 pub mod arena_allocator;
+pub mod checked_allocator;
+pub mod combinators;
+pub mod downward_arena_allocator;
 pub mod glibc_allocator;
+pub mod guard_page_allocator;
 pub mod jemalloc_allocator;
+pub mod latency_allocator;
 pub mod mimalloc_allocator;
 pub mod sbrk_allocator;
+pub mod thread_cache_allocator;
+pub mod thread_safe_allocator;
 pub mod verbose_allocator;
@@ -1,42 +1,104 @@
-use libc::{c_int, c_void, sbrk};
+use alloc_fmt::alloc_debug;
+use libc::{c_void, sbrk};
 use std::{
     alloc::{AllocError, Allocator, Layout},
-    cell::Cell,
+    cell::{Cell, OnceCell},
     ptr::NonNull,
+    rc::Rc,
 };
 
-#[derive(Clone)]
+use crate::allocators::combinators::Owns;
+
+/// A bump allocator moving the program break with `sbrk`. Clones share the same
+/// region, which is given back once the last of them is dropped if nothing got
+/// memory from `sbrk` after it.
 pub struct SbrkAllocator {
-    inner: Cell<Inner>,
+    /// Created on first use, so that `new` can be `const`.
+    inner: OnceCell<Rc<Shared>>,
 }
 
-#[derive(Copy)]
+/// Maximum number of separate regions an allocator can get from `sbrk`.
+const MAX_REGIONS: usize = 16;
+
+struct Shared {
+    current: Cell<Inner>,
+    /// The regions got from `sbrk` so far, the last one being the current one.
+    regions: [Cell<Region>; MAX_REGIONS],
+    len: Cell<usize>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Region {
+    start: usize,
+    end: usize,
+}
+
+#[derive(Clone, Copy)]
 struct Inner {
+    /// Start of the current region.
     arena: *mut u8,
     size: usize,
     offset: usize,
 }
 
-impl Clone for Inner {
+impl SbrkAllocator {
+    pub const fn new() -> Self {
+        SbrkAllocator {
+            inner: OnceCell::new(),
+        }
+    }
+
+    fn shared(&self) -> &Rc<Shared> {
+        self.inner.get_or_init(|| {
+            Rc::new(Shared {
+                current: Cell::new(Inner {
+                    arena: std::ptr::null_mut(),
+                    size: 0,
+                    offset: 0,
+                }),
+                regions: Default::default(),
+                len: Cell::new(0),
+            })
+        })
+    }
+}
+
+impl Clone for SbrkAllocator {
     fn clone(&self) -> Self {
-        Self {
-            arena: self.arena,
-            size: self.size,
-            offset: 0,
+        // Create the region state now, for the clone to share it.
+        SbrkAllocator {
+            inner: OnceCell::from(Rc::clone(self.shared())),
         }
     }
 }
 
-impl SbrkAllocator {
-    pub const fn new() -> Self {
-        let allocator = SbrkAllocator {
-            inner: Cell::new(Inner {
-                arena: std::ptr::null_mut(),
-                size: 0,
-                offset: 0,
-            }),
-        };
-        allocator
+impl Drop for Shared {
+    /// Gives back the regions at the top of the heap, the break only moving down
+    /// over memory of this allocator.
+    fn drop(&mut self) {
+        for region in self.regions[..self.len.get()].iter().rev() {
+            let region = region.get();
+            if unsafe { sbrk(0) } as usize != region.end {
+                break;
+            }
+            let Ok(decrement) = isize::try_from(region.end - region.start) else {
+                break;
+            };
+            // `sbrk` takes a `c_int` on some platforms and an `intptr_t` on others.
+            #[allow(clippy::useless_conversion)]
+            let Some(decrement) = (-decrement).try_into().ok() else {
+                break;
+            };
+            if unsafe { sbrk(decrement) } == -1isize as *mut c_void {
+                break;
+            }
+        }
+    }
+}
+
+impl Default for SbrkAllocator {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
@@ -49,55 +111,110 @@ fn align_up(offset: usize, align: usize) -> usize {
 
 unsafe impl Allocator for SbrkAllocator {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
-        let Inner {
-            size: initial_size,
-            offset: initial_offset,
-            ..
-        } = self.inner.get();
         let size = layout.size();
         let align = layout.align();
-        let new_offset = align_up(initial_offset, align);
+        loop {
+            let inner = self.shared().current.get();
+            // Align the address rather than the offset, the break being byte-aligned.
+            let base = inner.arena as usize;
+            let new_offset = align_up(base + inner.offset, align) - base;
+
+            // Zero-sized blocks are kept off the end of the region too, where
+            // the memory of whoever moves the break next starts.
+            if !inner.arena.is_null() && new_offset + size.max(1) <= inner.size {
+                self.shared().current.set(Inner {
+                    offset: new_offset + size,
+                    ..inner
+                });
+                let ptr = unsafe { inner.arena.add(new_offset) };
+                return Ok(NonNull::slice_from_raw_parts(
+                    NonNull::new(ptr).unwrap(),
+                    size,
+                ));
+            }
 
-        if new_offset + size > initial_size {
-            let missing_size = new_offset + size - initial_size;
+            // Leave room for the alignment padding, in case the new memory does
+            // not follow the current region.
+            let missing_size = (new_offset + size.max(1)).saturating_sub(inner.size) + align;
             let new_allocation_size = align_up(missing_size, PAGE_SIZE);
-            self.increase_heap_size(new_allocation_size as isize)?;
+            self.increase_heap_size(new_allocation_size.try_into().map_err(|_| AllocError)?)?;
         }
+    }
 
-        self.inner.set(Inner {
-            offset: new_offset + size,
-
-            ..self.inner.get()
-        });
-        let ptr = unsafe { self.inner.get().arena.add(new_offset) };
-        Ok(NonNull::slice_from_raw_parts(
-            NonNull::new(ptr).unwrap(),
-            size,
-        ))
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // sbrk does not provide a straightforward way to release memory back to
+        // the OS, but the last block can be reused.
+        let inner = self.shared().current.get();
+        if ptr.as_ptr().wrapping_add(layout.size()) == inner.arena.wrapping_add(inner.offset) {
+            self.shared().current.set(Inner {
+                offset: ptr.as_ptr().offset_from(inner.arena) as usize,
+                ..inner
+            });
+        }
     }
+}
 
-    unsafe fn deallocate(&self, _ptr: NonNull<u8>, _layout: Layout) {
-        // No-op: sbrk does not provide a straightforward way to release memory back to the OS.
+impl Owns for SbrkAllocator {
+    /// Whether `ptr` is in one of the regions this allocator got from `sbrk`.
+    /// Memory that other code got from `sbrk` in between is not claimed.
+    fn owns(&self, ptr: NonNull<u8>) -> bool {
+        let Some(shared) = self.inner.get() else {
+            return false;
+        };
+        let ptr = ptr.as_ptr() as usize;
+        shared.regions[..shared.len.get()]
+            .iter()
+            .any(|region| region.get().start <= ptr && ptr < region.get().end)
     }
 }
 
 impl SbrkAllocator {
+    /// Moves the break by `size` bytes, extending the current region if
+    /// nothing else moved the break since, and starting a new one otherwise.
+    /// Fails rather than start more than `MAX_REGIONS` regions, so that `owns`
+    /// stays exact.
     pub fn increase_heap_size(&self, size: isize) -> Result<(), AllocError> {
-        println!("increase_heap_size by {}", size);
-        let ptr = unsafe { sbrk(size as c_int) };
+        alloc_debug!("increase_heap_size by {}", size);
+        let shared = self.shared();
+        let inner = shared.current.get();
+        let end = inner.arena.wrapping_add(inner.size);
+        let len = shared.len.get();
+        if len == MAX_REGIONS && unsafe { sbrk(0) } as *mut u8 != end {
+            return Err(AllocError);
+        }
+        // `sbrk` takes a `c_int` on some platforms and an `intptr_t` on others.
+        #[allow(clippy::useless_conversion)]
+        let increment = size.try_into().map_err(|_| AllocError)?;
+        let ptr = unsafe { sbrk(increment) };
         if ptr == -1isize as *mut c_void {
             return Err(AllocError);
         }
-        let inner = self.inner.get();
-        self.inner.set(Inner {
-            arena: ptr as *mut u8,
-            size: inner.size.saturating_add_signed(size),
-            offset: inner.offset,
-        });
+        let ptr = ptr as *mut u8;
+        if !inner.arena.is_null() && end == ptr {
+            let size = inner.size.saturating_add_signed(size);
+            shared.current.set(Inner { size, ..inner });
+            shared.regions[len - 1].set(Region {
+                start: inner.arena as usize,
+                end: inner.arena as usize + size,
+            });
+        } else if len == MAX_REGIONS {
+            // The break moved between the two calls. The new memory is leaked,
+            // as it cannot be given back without knowing who moved the break.
+            return Err(AllocError);
+        } else {
+            // Something else moved the break since the last call: start a new region.
+            let size = size.max(0) as usize;
+            shared.current.set(Inner {
+                arena: ptr,
+                size,
+                offset: 0,
+            });
+            shared.regions[len].set(Region {
+                start: ptr as usize,
+                end: ptr as usize + size,
+            });
+            shared.len.set(len + 1);
+        }
         Ok(())
     }
 }
-
-impl Drop for SbrkAllocator {
-    fn drop(&mut self) {}
-}
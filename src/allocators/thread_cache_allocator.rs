@@ -0,0 +1,403 @@
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::{Cell, RefCell},
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicUsize, Ordering::Relaxed},
+        Arc,
+    },
+};
+
+/// Smallest size class is 16 bytes, the largest one 32 KiB.
+const MIN_CLASS_SHIFT: u32 = 4;
+const NUM_CLASSES: usize = 12;
+const MAX_CACHED_SIZE: usize = 1 << (MIN_CLASS_SHIFT as usize + NUM_CLASSES - 1);
+/// Alignment of every cached block. Requests with a larger alignment bypass the cache.
+const CLASS_ALIGN: usize = 16;
+
+const MAGAZINE_CAPACITY: usize = 64;
+/// Number of blocks moved between a magazine and the backing allocator at once.
+const BATCH_SIZE: usize = MAGAZINE_CAPACITY / 2;
+
+/// Number of direct-mapped slots in front of the list of a thread's caches.
+const CACHE_SLOTS: usize = 8;
+/// Id of an empty slot, never given to a cache.
+const NO_CACHE: usize = usize::MAX;
+
+static NEXT_CACHE_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Magazines of every `ThreadCache` used by the current thread.
+    static LOCAL_CACHES: LocalCaches = const {
+        LocalCaches {
+            slots: [const { Cell::new((NO_CACHE, std::ptr::null_mut())) }; CACHE_SLOTS],
+            caches: RefCell::new(Vec::new()),
+        }
+    };
+}
+
+/// The caches of a thread, looked up by cache id.
+///
+/// A cache is found in the slot `id % CACHE_SLOTS` when it was the last one
+/// used there, which only costs a comparison; otherwise it is searched in the
+/// list owning all of them, and put back in the slot.
+struct LocalCaches {
+    slots: [Cell<(usize, *mut ())>; CACHE_SLOTS],
+    caches: RefCell<Vec<OwnedCache>>,
+}
+
+/// A `LocalCache<A>` with its type erased.
+struct OwnedCache {
+    id: usize,
+    cache: *mut (),
+    drop: unsafe fn(*mut ()),
+}
+
+unsafe fn drop_cache<A: Allocator + Sync>(cache: *mut ()) {
+    drop(Box::from_raw(cache as *mut LocalCache<A>));
+}
+
+/// A thread-local caching front end over any thread-safe allocator.
+///
+/// Small blocks are served from per-thread, per-size-class magazines which are
+/// refilled from and flushed to the backing allocator in batches. A block freed
+/// by another thread than the one that allocated it simply goes to the freeing
+/// thread's magazines, which is why the backing allocator has to be `Sync`.
+///
+/// Clones share the same per-thread caches. The magazines of a thread are flushed
+/// back to the backing allocator when the thread exits.
+pub struct ThreadCache<A: Allocator + Sync> {
+    shared: Arc<Shared<A>>,
+}
+
+struct Shared<A> {
+    id: usize,
+    inner: A,
+}
+
+struct Magazine {
+    blocks: [*mut u8; MAGAZINE_CAPACITY],
+    len: usize,
+}
+
+struct LocalCache<A: Allocator + Sync> {
+    shared: Arc<Shared<A>>,
+    magazines: [Magazine; NUM_CLASSES],
+}
+
+/// Returns the size class serving `layout`, if it can be cached.
+#[inline]
+fn size_class(layout: Layout) -> Option<usize> {
+    if layout.size() > MAX_CACHED_SIZE || layout.align() > CLASS_ALIGN {
+        return None;
+    }
+    let size = layout.size().max(1 << MIN_CLASS_SHIFT).next_power_of_two();
+    Some((size.trailing_zeros() - MIN_CLASS_SHIFT) as usize)
+}
+
+/// Layout of the blocks requested from the backing allocator for `class`.
+#[inline]
+fn class_layout(class: usize) -> Layout {
+    let size = 1 << (class as u32 + MIN_CLASS_SHIFT);
+    unsafe { Layout::from_size_align_unchecked(size, CLASS_ALIGN) }
+}
+
+impl<A: Allocator + Sync> ThreadCache<A> {
+    pub fn new(inner: A) -> Self {
+        ThreadCache {
+            shared: Arc::new(Shared {
+                id: NEXT_CACHE_ID.fetch_add(1, Relaxed),
+                inner,
+            }),
+        }
+    }
+}
+
+impl<A: Allocator + Sync + Send + 'static> ThreadCache<A> {
+    /// Returns the magazines of the calling thread to the backing allocator.
+    pub fn flush(&self) {
+        let _ = LOCAL_CACHES.try_with(|caches| caches.remove(self.shared.id));
+    }
+
+    /// Runs `f` on the calling thread's cache, creating it on first use.
+    ///
+    /// Returns `None` while the thread-local storage is being torn down.
+    #[inline]
+    fn with_local<R>(&self, f: impl FnOnce(&mut LocalCache<A>) -> R) -> Option<R> {
+        LOCAL_CACHES
+            .try_with(|caches| {
+                let cache = caches.get(&self.shared)?;
+                // The id is only ever given to `self.shared`, so the cache is a
+                // `LocalCache<A>`. `f` cannot reach it again: it only calls the
+                // backing allocator, which does not contain this `ThreadCache`.
+                Some(f(unsafe { &mut *(cache as *mut LocalCache<A>) }))
+            })
+            .ok()
+            .flatten()
+    }
+}
+
+impl LocalCaches {
+    #[inline]
+    fn get<A: Allocator + Sync>(&self, shared: &Arc<Shared<A>>) -> Option<*mut ()> {
+        let (id, cache) = self.slots[shared.id % CACHE_SLOTS].get();
+        if id == shared.id {
+            return Some(cache);
+        }
+        self.get_slow(shared)
+    }
+
+    #[cold]
+    fn get_slow<A: Allocator + Sync>(&self, shared: &Arc<Shared<A>>) -> Option<*mut ()> {
+        let mut caches = self.caches.try_borrow_mut().ok()?;
+        let cache = match caches.iter().find(|owned| owned.id == shared.id) {
+            Some(owned) => owned.cache,
+            None => {
+                let cache = Box::into_raw(Box::new(LocalCache::new(Arc::clone(shared))));
+                caches.push(OwnedCache {
+                    id: shared.id,
+                    cache: cache as *mut (),
+                    drop: drop_cache::<A>,
+                });
+                cache as *mut ()
+            }
+        };
+        self.slots[shared.id % CACHE_SLOTS].set((shared.id, cache));
+        Some(cache)
+    }
+
+    /// Drops the cache `id`, which flushes it.
+    fn remove(&self, id: usize) {
+        let Ok(mut caches) = self.caches.try_borrow_mut() else {
+            return;
+        };
+        let Some(index) = caches.iter().position(|owned| owned.id == id) else {
+            return;
+        };
+        let owned = caches.swap_remove(index);
+        drop(caches);
+        let slot = &self.slots[id % CACHE_SLOTS];
+        if slot.get().0 == id {
+            slot.set((NO_CACHE, std::ptr::null_mut()));
+        }
+        unsafe { (owned.drop)(owned.cache) };
+    }
+}
+
+impl Drop for LocalCaches {
+    fn drop(&mut self) {
+        for owned in self.caches.take() {
+            unsafe { (owned.drop)(owned.cache) };
+        }
+    }
+}
+
+impl<A: Allocator + Sync> Clone for ThreadCache<A> {
+    fn clone(&self) -> Self {
+        ThreadCache {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<A: Allocator + Sync> LocalCache<A> {
+    fn new(shared: Arc<Shared<A>>) -> Self {
+        LocalCache {
+            shared,
+            magazines: std::array::from_fn(|_| Magazine {
+                blocks: [std::ptr::null_mut(); MAGAZINE_CAPACITY],
+                len: 0,
+            }),
+        }
+    }
+
+    #[inline]
+    fn allocate(&mut self, class: usize) -> Result<NonNull<u8>, AllocError> {
+        if self.magazines[class].len == 0 {
+            self.refill(class)?;
+        }
+        let magazine = &mut self.magazines[class];
+        magazine.len -= 1;
+        Ok(unsafe { NonNull::new_unchecked(magazine.blocks[magazine.len]) })
+    }
+
+    #[inline]
+    fn deallocate(&mut self, class: usize, ptr: NonNull<u8>) {
+        if self.magazines[class].len == MAGAZINE_CAPACITY {
+            self.flush(class, BATCH_SIZE);
+        }
+        let magazine = &mut self.magazines[class];
+        magazine.blocks[magazine.len] = ptr.as_ptr();
+        magazine.len += 1;
+    }
+
+    #[cold]
+    fn refill(&mut self, class: usize) -> Result<(), AllocError> {
+        let layout = class_layout(class);
+        let magazine = &mut self.magazines[class];
+        for _ in 0..BATCH_SIZE {
+            match self.shared.inner.allocate(layout) {
+                Ok(block) => {
+                    magazine.blocks[magazine.len] = block.cast::<u8>().as_ptr();
+                    magazine.len += 1;
+                }
+                Err(_) if magazine.len > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Releases the `count` least recently freed blocks of `class`.
+    #[cold]
+    fn flush(&mut self, class: usize, count: usize) {
+        let layout = class_layout(class);
+        let magazine = &mut self.magazines[class];
+        let count = count.min(magazine.len);
+        for &block in &magazine.blocks[..count] {
+            unsafe {
+                self.shared
+                    .inner
+                    .deallocate(NonNull::new_unchecked(block), layout)
+            };
+        }
+        magazine.blocks.copy_within(count..magazine.len, 0);
+        magazine.len -= count;
+    }
+}
+
+impl<A: Allocator + Sync> Drop for LocalCache<A> {
+    fn drop(&mut self) {
+        for class in 0..NUM_CLASSES {
+            self.flush(class, MAGAZINE_CAPACITY);
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Sync + Send + 'static> Allocator for ThreadCache<A> {
+    #[inline]
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let Some(class) = size_class(layout) else {
+            return self.shared.inner.allocate(layout);
+        };
+        let ptr = match self.with_local(|cache| cache.allocate(class)) {
+            Some(result) => result?,
+            None => self.shared.inner.allocate(class_layout(class))?.cast(),
+        };
+        Ok(NonNull::slice_from_raw_parts(
+            ptr,
+            class_layout(class).size(),
+        ))
+    }
+
+    #[inline]
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        let Some(class) = size_class(layout) else {
+            return self.shared.inner.deallocate(ptr, layout);
+        };
+        if self
+            .with_local(|cache| cache.deallocate(class, ptr))
+            .is_none()
+        {
+            self.shared.inner.deallocate(ptr, class_layout(class));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, sync::atomic::AtomicIsize, thread};
+
+    use super::*;
+
+    /// Counts the blocks live in `System`.
+    #[derive(Clone, Default)]
+    struct Counting {
+        live: Arc<AtomicIsize>,
+    }
+
+    impl Counting {
+        fn live(&self) -> isize {
+            self.live.load(Relaxed)
+        }
+    }
+
+    unsafe impl Allocator for Counting {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            self.live.fetch_add(1, Relaxed);
+            System.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            self.live.fetch_sub(1, Relaxed);
+            System.deallocate(ptr, layout)
+        }
+    }
+
+    const SMALL: Layout = unsafe { Layout::from_size_align_unchecked(24, 8) };
+
+    fn allocate(cache: &ThreadCache<Counting>, count: usize) -> Vec<NonNull<u8>> {
+        (0..count)
+            .map(|_| cache.allocate(SMALL).unwrap().cast())
+            .collect()
+    }
+
+    fn deallocate(cache: &ThreadCache<Counting>, blocks: Vec<NonNull<u8>>) {
+        for block in blocks {
+            unsafe { cache.deallocate(block, SMALL) };
+        }
+    }
+
+    #[test]
+    fn refills_in_batches() {
+        let backing = Counting::default();
+        let cache = ThreadCache::new(backing.clone());
+        let first = allocate(&cache, 1);
+        assert_eq!(backing.live(), BATCH_SIZE as isize);
+        let rest = allocate(&cache, BATCH_SIZE - 1);
+        assert_eq!(backing.live(), BATCH_SIZE as isize);
+        let extra = allocate(&cache, 1);
+        assert_eq!(backing.live(), 2 * BATCH_SIZE as isize);
+        deallocate(&cache, first);
+        deallocate(&cache, rest);
+        deallocate(&cache, extra);
+        cache.flush();
+        assert_eq!(backing.live(), 0);
+    }
+
+    #[test]
+    fn flushes_full_magazines_in_batches() {
+        let backing = Counting::default();
+        let cache = ThreadCache::new(backing.clone());
+        let blocks = allocate(&cache, MAGAZINE_CAPACITY + 1);
+        let allocated = backing.live();
+        deallocate(&cache, blocks);
+        // Freeing into the full magazine released half of it first.
+        assert_eq!(backing.live(), allocated - BATCH_SIZE as isize);
+        cache.flush();
+        assert_eq!(backing.live(), 0);
+    }
+
+    #[test]
+    fn flushes_on_thread_exit() {
+        let backing = Counting::default();
+        let cache = ThreadCache::new(backing.clone());
+        let thread_cache = cache.clone();
+        thread::spawn(move || deallocate(&thread_cache, allocate(&thread_cache, 10)))
+            .join()
+            .unwrap();
+        assert_eq!(backing.live(), 0);
+    }
+
+    #[test]
+    fn caches_sharing_a_slot() {
+        let backings: Vec<_> = (0..2 * CACHE_SLOTS).map(|_| Counting::default()).collect();
+        let caches: Vec<_> = backings.iter().cloned().map(ThreadCache::new).collect();
+        let blocks: Vec<_> = caches.iter().map(|cache| allocate(cache, 3)).collect();
+        for (cache, blocks) in caches.iter().zip(blocks) {
+            deallocate(cache, blocks);
+            cache.flush();
+        }
+        assert!(backings.iter().all(|backing| backing.live() == 0));
+    }
+}
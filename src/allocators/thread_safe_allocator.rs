@@ -0,0 +1,786 @@
+//! Adapters making an allocator usable from several threads at once.
+//!
+//! - [`SharedAllocator`]: no synchronization, for allocators that are already `Sync`.
+//! - [`SpinLockAllocator`]: every call goes through a single spin lock.
+//! - [`ShardedAllocator`]: one locked instance per shard, picked by thread index.
+//! - [`PerThreadAllocator`]: one unlocked instance per thread, blocks freed by other
+//!   threads being handed back to the instance they come from.
+//!
+//! All of them are cheap to clone, clones share the same underlying allocator(s).
+//! Allocators that are not `Send`, like the arenas, can be put behind them with
+//! [`Unshared`].
+use std::{
+    alloc::{AllocError, Allocator, Layout},
+    cell::{RefCell, UnsafeCell},
+    ptr::NonNull,
+    sync::{
+        atomic::{
+            AtomicBool, AtomicUsize,
+            Ordering::{Acquire, Relaxed, Release},
+        },
+        Arc, OnceLock,
+    },
+};
+
+use spin::Mutex;
+
+static NEXT_THREAD_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static THREAD_INDEX: usize = NEXT_THREAD_INDEX.fetch_add(1, Relaxed);
+}
+
+/// Returns a small integer identifying the calling thread, in order of first use.
+///
+/// Returns 0 once the thread-local storage of the thread is being torn down.
+pub(crate) fn thread_index() -> usize {
+    THREAD_INDEX.try_with(|index| *index).unwrap_or(0)
+}
+
+/// Owns an allocator that is not `Send` only because its clones share state
+/// through an `Rc`, like the arenas, so that a locking adapter can own it too.
+/// Also turns `bumpalo::Bump`, whose references are the allocator, into one.
+pub struct Unshared<A> {
+    inner: A,
+}
+
+impl<A> Unshared<A> {
+    /// # Safety
+    ///
+    /// No clone of `inner` may be used while the returned value lives, since it
+    /// can be used and dropped on any thread.
+    pub unsafe fn new(inner: A) -> Self {
+        Unshared { inner }
+    }
+}
+
+unsafe impl<A> Send for Unshared<A> {}
+
+unsafe impl<A> Allocator for Unshared<A>
+where
+    for<'a> &'a A: Allocator,
+{
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (&self.inner).allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        (&self.inner).allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        (&self.inner).deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (&self.inner).grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (&self.inner).grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        (&self.inner).shrink(ptr, old_layout, new_layout)
+    }
+}
+
+/// Shares an allocator that is already thread-safe between threads, without locking.
+pub struct SharedAllocator<A: Allocator + Sync> {
+    inner: Arc<A>,
+}
+
+impl<A: Allocator + Sync> SharedAllocator<A> {
+    pub fn new(inner: A) -> Self {
+        SharedAllocator {
+            inner: Arc::new(inner),
+        }
+    }
+}
+
+impl<A: Allocator + Sync> Clone for SharedAllocator<A> {
+    fn clone(&self) -> Self {
+        SharedAllocator {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Sync> Allocator for SharedAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.shrink(ptr, old_layout, new_layout)
+    }
+}
+
+/// Serializes every call to the inner allocator with a spin lock.
+pub struct SpinLockAllocator<A: Allocator + Send> {
+    inner: Arc<Mutex<A>>,
+}
+
+impl<A: Allocator + Send> SpinLockAllocator<A> {
+    pub fn new(inner: A) -> Self {
+        SpinLockAllocator {
+            inner: Arc::new(Mutex::new(inner)),
+        }
+    }
+}
+
+impl<A: Allocator + Send> Clone for SpinLockAllocator<A> {
+    fn clone(&self) -> Self {
+        SpinLockAllocator {
+            inner: Arc::clone(&self.inner),
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Send> Allocator for SpinLockAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.lock().allocate(layout)
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.lock().allocate_zeroed(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.inner.lock().deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.lock().grow(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.lock().grow_zeroed(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.inner.lock().shrink(ptr, old_layout, new_layout)
+    }
+}
+
+const HEADER_SIZE: usize = std::mem::size_of::<usize>();
+
+/// Layout of the block requested from the inner allocator, and offset of the user
+/// pointer in it, leaving room for a header recording the owner of the block.
+fn inner_layout(layout: Layout) -> Result<(Layout, usize), AllocError> {
+    let offset = HEADER_SIZE.next_multiple_of(layout.align());
+    let size = layout.size().checked_add(offset).ok_or(AllocError)?;
+    let align = layout.align().max(std::mem::align_of::<usize>());
+    let layout = Layout::from_size_align(size, align).map_err(|_| AllocError)?;
+    Ok((layout, offset))
+}
+
+/// Writes the owner header of `block` and returns the user part of it.
+unsafe fn finish(block: NonNull<[u8]>, offset: usize, owner: usize) -> NonNull<[u8]> {
+    let ptr = block.cast::<u8>().add(offset);
+    ptr.cast::<usize>().sub(1).write(owner);
+    NonNull::slice_from_raw_parts(ptr, block.len() - offset)
+}
+
+unsafe fn owner(ptr: NonNull<u8>) -> usize {
+    ptr.cast::<usize>().sub(1).read()
+}
+
+/// Spreads threads over several instances of the inner allocator.
+///
+/// Each instance is created on first use and protected by its own spin lock, so
+/// threads mapped to different shards never contend. Every block carries a small
+/// header recording the shard it comes from, which allows freeing it from any thread.
+pub struct ShardedAllocator<A: Allocator + Send> {
+    shards: Arc<Shards<A>>,
+}
+
+struct Shards<A> {
+    instances: Box<[OnceLock<Mutex<A>>]>,
+    make: Box<dyn Fn() -> A + Send + Sync>,
+}
+
+impl<A: Allocator + Send> ShardedAllocator<A> {
+    /// Creates `shards` instances with `make`, thread `i` using shard `i % shards`.
+    pub fn new(shards: usize, make: impl Fn() -> A + Send + Sync + 'static) -> Self {
+        assert!(shards > 0, "ShardedAllocator needs at least one shard");
+        ShardedAllocator {
+            shards: Arc::new(Shards {
+                instances: (0..shards).map(|_| OnceLock::new()).collect(),
+                make: Box::new(make),
+            }),
+        }
+    }
+
+    fn shard(&self, index: usize) -> &Mutex<A> {
+        self.shards.instances[index].get_or_init(|| Mutex::new((self.shards.make)()))
+    }
+
+    fn allocate_with(
+        &self,
+        layout: Layout,
+        allocate: impl FnOnce(&A, Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (inner_layout, offset) = inner_layout(layout)?;
+        let index = thread_index() % self.shards.instances.len();
+        let block = allocate(&self.shard(index).lock(), inner_layout)?;
+        Ok(unsafe { finish(block, offset, index) })
+    }
+
+    /// Resizes a block in its owner shard, or moves it when the header offset changes.
+    unsafe fn resize_with(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        resize: impl FnOnce(&A, NonNull<u8>, Layout, Layout) -> Result<NonNull<[u8]>, AllocError>,
+        allocate: impl FnOnce(&Self, Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (old_inner, old_offset) = inner_layout(old_layout)?;
+        let (new_inner, new_offset) = inner_layout(new_layout)?;
+        if old_offset != new_offset {
+            let new_ptr = allocate(self, new_layout)?;
+            let count = old_layout.size().min(new_layout.size());
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.cast::<u8>().as_ptr(), count);
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+        let index = owner(ptr);
+        let block = resize(
+            &self.shard(index).lock(),
+            ptr.sub(old_offset),
+            old_inner,
+            new_inner,
+        )?;
+        Ok(finish(block, new_offset, index))
+    }
+}
+
+impl<A: Allocator + Send> Clone for ShardedAllocator<A> {
+    fn clone(&self) -> Self {
+        ShardedAllocator {
+            shards: Arc::clone(&self.shards),
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Send> Allocator for ShardedAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, |shard, layout| shard.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, |shard, layout| shard.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // `inner_layout` cannot fail here since it succeeded when allocating.
+        let (inner_layout, offset) = inner_layout(layout).unwrap_unchecked();
+        self.shard(owner(ptr))
+            .lock()
+            .deallocate(ptr.sub(offset), inner_layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |shard, ptr, old, new| shard.grow(ptr, old, new),
+            |this, layout| this.allocate(layout),
+        )
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |shard, ptr, old, new| shard.grow_zeroed(ptr, old, new),
+            |this, layout| this.allocate_zeroed(layout),
+        )
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |shard, ptr, old, new| shard.shrink(ptr, old, new),
+            |this, layout| this.allocate(layout),
+        )
+    }
+}
+
+static NEXT_PER_THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Instances of every `PerThreadAllocator` used by the current thread.
+    static LOCAL_INSTANCES: LocalInstances = const { LocalInstances(RefCell::new(Vec::new())) };
+}
+
+struct LocalInstances(RefCell<Vec<LocalInstance>>);
+
+/// An `Arc<Instance<A>>` with its type erased.
+struct LocalInstance {
+    id: usize,
+    ptr: *const (),
+    instance: Arc<dyn Orphan>,
+}
+
+/// Lets the instances of an exiting thread be used by the threads freeing their blocks.
+trait Orphan {
+    fn orphan(&self);
+}
+
+impl Drop for LocalInstances {
+    fn drop(&mut self) {
+        for local in self.0.take() {
+            local.instance.orphan();
+        }
+    }
+}
+
+/// Gives each thread an instance of the inner allocator of its own, used without
+/// any locking.
+///
+/// Every block carries a header pointing to the instance it comes from. A block
+/// freed by another thread is queued on that instance, and freed by its thread on
+/// its next allocation. Once a thread exits, its instance is freed into directly,
+/// under the lock of the queue. Resizing a block of another thread moves it to the
+/// calling thread's instance.
+///
+/// Instances live as long as the allocator and its clones, so arenas keep every
+/// block they handed out until then.
+pub struct PerThreadAllocator<A: Allocator + Send + 'static> {
+    shared: Arc<PerThreadShared<A>>,
+}
+
+struct PerThreadShared<A> {
+    id: usize,
+    make: Box<dyn Fn() -> A + Send + Sync>,
+    instances: Mutex<Vec<Arc<Instance<A>>>>,
+}
+
+struct Instance<A> {
+    /// Only used by the thread owning the instance, or under `remote` once it is
+    /// orphaned.
+    allocator: UnsafeCell<A>,
+    /// Whether `remote` has blocks, so that the owner does not take the lock for
+    /// nothing.
+    pending: AtomicBool,
+    remote: Mutex<Remote>,
+}
+
+#[derive(Default)]
+struct Remote {
+    blocks: Vec<(NonNull<u8>, Layout)>,
+    orphaned: bool,
+}
+
+unsafe impl<A: Send> Send for Instance<A> {}
+unsafe impl<A: Send> Sync for Instance<A> {}
+
+impl<A: Allocator> Instance<A> {
+    /// Frees the blocks other threads queued. Only called by the owner thread.
+    unsafe fn free_pending(&self) {
+        if !self.pending.load(Acquire) {
+            return;
+        }
+        let blocks = {
+            let mut remote = self.remote.lock();
+            self.pending.store(false, Relaxed);
+            std::mem::take(&mut remote.blocks)
+        };
+        for (ptr, layout) in blocks {
+            (*self.allocator.get()).deallocate(ptr, layout);
+        }
+    }
+
+    /// Frees a block from another thread than the owner.
+    unsafe fn free_remote(&self, ptr: NonNull<u8>, layout: Layout) {
+        let mut remote = self.remote.lock();
+        if remote.orphaned {
+            (*self.allocator.get()).deallocate(ptr, layout);
+        } else {
+            remote.blocks.push((ptr, layout));
+            self.pending.store(true, Release);
+        }
+    }
+}
+
+impl<A: Allocator> Orphan for Instance<A> {
+    fn orphan(&self) {
+        let mut remote = self.remote.lock();
+        remote.orphaned = true;
+        for (ptr, layout) in std::mem::take(&mut remote.blocks) {
+            unsafe { (*self.allocator.get()).deallocate(ptr, layout) };
+        }
+    }
+}
+
+impl<A: Allocator + Send + 'static> PerThreadAllocator<A> {
+    /// Creates the instance of each thread with `make`, on its first allocation.
+    pub fn new(make: impl Fn() -> A + Send + Sync + 'static) -> Self {
+        PerThreadAllocator {
+            shared: Arc::new(PerThreadShared {
+                id: NEXT_PER_THREAD_ID.fetch_add(1, Relaxed),
+                make: Box::new(make),
+                instances: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Returns the calling thread's instance, if it has one.
+    ///
+    /// Returns `None` while the thread-local storage is being torn down.
+    fn find(&self) -> Option<&Instance<A>> {
+        let ptr = LOCAL_INSTANCES
+            .try_with(|locals| {
+                let locals = locals.0.borrow();
+                let local = locals.iter().find(|local| local.id == self.shared.id)?;
+                Some(local.ptr)
+            })
+            .ok()
+            .flatten()?;
+        // `self.shared` keeps the instance alive.
+        Some(unsafe { &*(ptr as *const Instance<A>) })
+    }
+
+    /// Returns the calling thread's instance, creating it on first use.
+    fn local(&self) -> Option<&Instance<A>> {
+        if let Some(instance) = self.find() {
+            return Some(instance);
+        }
+        let instance = Arc::new(Instance {
+            allocator: UnsafeCell::new((self.shared.make)()),
+            pending: AtomicBool::new(false),
+            remote: Mutex::new(Remote::default()),
+        });
+        let ptr = Arc::as_ptr(&instance);
+        LOCAL_INSTANCES
+            .try_with(|locals| {
+                locals.0.borrow_mut().push(LocalInstance {
+                    id: self.shared.id,
+                    ptr: ptr as *const (),
+                    instance: instance.clone(),
+                })
+            })
+            .ok()?;
+        self.shared.instances.lock().push(instance);
+        Some(unsafe { &*ptr })
+    }
+
+    fn is_local(&self, instance: &Instance<A>) -> bool {
+        self.find()
+            .is_some_and(|local| std::ptr::eq(local, instance))
+    }
+
+    fn allocate_with(
+        &self,
+        layout: Layout,
+        allocate: impl FnOnce(&A, Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (inner_layout, offset) = inner_layout(layout)?;
+        let instance = self.local().ok_or(AllocError)?;
+        unsafe {
+            instance.free_pending();
+            let block = allocate(&*instance.allocator.get(), inner_layout)?;
+            Ok(finish(
+                block,
+                offset,
+                instance as *const Instance<A> as usize,
+            ))
+        }
+    }
+
+    /// Resizes a block of the calling thread's instance, or moves it when it comes
+    /// from another thread or the header offset changes.
+    unsafe fn resize_with(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+        resize: impl FnOnce(&A, NonNull<u8>, Layout, Layout) -> Result<NonNull<[u8]>, AllocError>,
+        allocate: impl FnOnce(&Self, Layout) -> Result<NonNull<[u8]>, AllocError>,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        let (old_inner, old_offset) = inner_layout(old_layout)?;
+        let (new_inner, new_offset) = inner_layout(new_layout)?;
+        let instance = &*(owner(ptr) as *const Instance<A>);
+        if old_offset != new_offset || !self.is_local(instance) {
+            let new_ptr = allocate(self, new_layout)?;
+            let count = old_layout.size().min(new_layout.size());
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.cast::<u8>().as_ptr(), count);
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+        let block = resize(
+            &*instance.allocator.get(),
+            ptr.sub(old_offset),
+            old_inner,
+            new_inner,
+        )?;
+        Ok(finish(
+            block,
+            new_offset,
+            instance as *const Instance<A> as usize,
+        ))
+    }
+}
+
+impl<A: Allocator + Send + 'static> Clone for PerThreadAllocator<A> {
+    fn clone(&self) -> Self {
+        PerThreadAllocator {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+unsafe impl<A: Allocator + Send + 'static> Allocator for PerThreadAllocator<A> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, |instance, layout| instance.allocate(layout))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        self.allocate_with(layout, |instance, layout| instance.allocate_zeroed(layout))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        // `inner_layout` cannot fail here since it succeeded when allocating.
+        let (inner_layout, offset) = inner_layout(layout).unwrap_unchecked();
+        let instance = &*(owner(ptr) as *const Instance<A>);
+        if self.is_local(instance) {
+            (*instance.allocator.get()).deallocate(ptr.sub(offset), inner_layout)
+        } else {
+            instance.free_remote(ptr.sub(offset), inner_layout)
+        }
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |instance, ptr, old, new| instance.grow(ptr, old, new),
+            |this, layout| this.allocate(layout),
+        )
+    }
+
+    unsafe fn grow_zeroed(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |instance, ptr, old, new| instance.grow_zeroed(ptr, old, new),
+            |this, layout| this.allocate_zeroed(layout),
+        )
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize_with(
+            ptr,
+            old_layout,
+            new_layout,
+            |instance, ptr, old, new| instance.shrink(ptr, old, new),
+            |this, layout| this.allocate(layout),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{alloc::System, collections::HashSet, sync::atomic::AtomicIsize, thread};
+
+    use super::*;
+
+    /// Allocates from `System`, and checks that it only frees its own blocks.
+    /// Counts the blocks live in all instances sharing `live`.
+    #[derive(Default)]
+    struct Owned {
+        blocks: RefCell<HashSet<usize>>,
+        live: Arc<AtomicIsize>,
+    }
+
+    unsafe impl Allocator for Owned {
+        fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+            let block = System.allocate(layout)?;
+            self.blocks
+                .borrow_mut()
+                .insert(block.cast::<u8>().as_ptr() as usize);
+            self.live.fetch_add(1, Relaxed);
+            Ok(block)
+        }
+
+        unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+            assert!(
+                self.blocks.borrow_mut().remove(&(ptr.as_ptr() as usize)),
+                "freed a block of another instance"
+            );
+            self.live.fetch_sub(1, Relaxed);
+            System.deallocate(ptr, layout)
+        }
+    }
+
+    const SMALL: Layout = unsafe { Layout::from_size_align_unchecked(24, 8) };
+
+    fn per_thread() -> (PerThreadAllocator<Owned>, Arc<AtomicIsize>) {
+        let live = Arc::new(AtomicIsize::new(0));
+        let shared = live.clone();
+        let allocator = PerThreadAllocator::new(move || Owned {
+            blocks: RefCell::default(),
+            live: shared.clone(),
+        });
+        (allocator, live)
+    }
+
+    /// Allocates `count` blocks on another thread, which then exits.
+    fn allocate_on_thread(allocator: &PerThreadAllocator<Owned>, count: usize) -> Vec<usize> {
+        let allocator = allocator.clone();
+        thread::spawn(move || {
+            (0..count)
+                .map(|_| allocator.allocate(SMALL).unwrap().cast::<u8>().as_ptr() as usize)
+                .collect()
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn remote_frees_wait_for_the_owner() {
+        let (allocator, live) = per_thread();
+        let blocks: Vec<_> = (0..10)
+            .map(|_| allocator.allocate(SMALL).unwrap().cast::<u8>().as_ptr() as usize)
+            .collect();
+        let remote = allocator.clone();
+        thread::spawn(move || {
+            for block in blocks {
+                unsafe { remote.deallocate(NonNull::new(block as *mut u8).unwrap(), SMALL) };
+            }
+        })
+        .join()
+        .unwrap();
+        assert_eq!(live.load(Relaxed), 10);
+        let block = allocator.allocate(SMALL).unwrap().cast();
+        assert_eq!(live.load(Relaxed), 1);
+        unsafe { allocator.deallocate(block, SMALL) };
+        assert_eq!(live.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn frees_into_instances_of_exited_threads() {
+        let (allocator, live) = per_thread();
+        let blocks = allocate_on_thread(&allocator, 10);
+        assert_eq!(live.load(Relaxed), 10);
+        for block in blocks {
+            unsafe { allocator.deallocate(NonNull::new(block as *mut u8).unwrap(), SMALL) };
+        }
+        assert_eq!(live.load(Relaxed), 0);
+    }
+
+    #[test]
+    fn resizing_moves_blocks_of_other_threads() {
+        let (allocator, live) = per_thread();
+        let block = allocate_on_thread(&allocator, 1)[0];
+        let ptr = NonNull::new(block as *mut u8).unwrap();
+        unsafe {
+            ptr.write(42);
+            let grown = Layout::from_size_align(100, 8).unwrap();
+            let ptr = allocator.grow(ptr, SMALL, grown).unwrap().cast::<u8>();
+            assert_eq!(ptr.read(), 42);
+            assert_eq!(live.load(Relaxed), 1);
+            allocator.deallocate(ptr, grown);
+        }
+        assert_eq!(live.load(Relaxed), 0);
+    }
+}
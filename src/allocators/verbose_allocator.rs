@@ -2,6 +2,11 @@ use std::{
     alloc::{AllocError, Allocator, Layout},
     ptr::NonNull,
 };
+
+use alloc_fmt::alloc_debug;
+
+/// Logs every allocation and deallocation at the `debug` level, with
+/// `VerboseAllocator` as target: run with `ALLOC_LOG=VerboseAllocator` to see them.
 pub struct VerboseAllocator<A: Allocator> {
     inner: A,
 }
@@ -15,20 +20,12 @@ impl<A: Allocator> VerboseAllocator<A> {
 unsafe impl<A: Allocator> Allocator for VerboseAllocator<A> {
     fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
         let ptr = self.inner.allocate(layout)?;
-        println!(
-            "[VerboseAllocator] Allocating {:?} bytes at {:p}",
-            layout.size(),
-            ptr
-        );
+        alloc_debug!(target: "VerboseAllocator", "Allocating {:?} bytes at {:p}", layout.size(), ptr);
         Ok(ptr)
     }
 
     unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
-        println!(
-            "[VerboseAllocator] Deallocating {:?} bytes at {:p}",
-            layout.size(),
-            ptr
-        );
+        alloc_debug!(target: "VerboseAllocator", "Deallocating {:?} bytes at {:p}", layout.size(), ptr);
         self.inner.deallocate(ptr, layout)
     }
 }
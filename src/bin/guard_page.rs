@@ -0,0 +1,28 @@
+//! Runs with every heap allocation guarded by an inaccessible page.
+//!
+//! Pass `overflow` or `use-after-free` to perform that bug and see the process
+//! crash with `SIGSEGV` right at the faulty access. `GuardMode::Underflow`
+//! catches accesses before the start of blocks instead.
+use memory_allocator_performance_rs::{GuardMode, GuardPageAllocator};
+
+#[global_allocator]
+static ALLOCATOR: GuardPageAllocator = GuardPageAllocator::new(GuardMode::Overflow);
+
+fn main() {
+    let mut v: Vec<u64> = (0..1000).collect();
+    v.retain(|x| x % 3 == 0);
+    let s: String = v.iter().map(|x| x.to_string()).collect();
+    println!("{} elements, {} digits", v.len(), s.len());
+
+    let bytes = vec![0u8; 100].into_boxed_slice();
+    let ptr = bytes.as_ptr() as *mut u8;
+    match std::env::args().nth(1).as_deref() {
+        Some("overflow") => unsafe { ptr.add(bytes.len()).write(1) },
+        Some("use-after-free") => {
+            drop(bytes);
+            unsafe { ptr.write(1) }
+        }
+        _ => return,
+    }
+    println!("the bad access went unnoticed");
+}
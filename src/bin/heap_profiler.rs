@@ -0,0 +1,34 @@
+//! Profiles a small workload, writing the profile given as argument when the
+//! process exits: `heap_profiler heap.pb` for `go tool pprof`, or
+//! `heap_profiler heap.folded` for folded stacks of the bytes allocated.
+//!
+//! Frames are only named for exported symbols; build with
+//! `RUSTFLAGS="-C link-args=-rdynamic"` to export those of the binary too.
+use memory_allocator_performance_rs::{HeapProfiler, ProfileFormat};
+use std::{alloc::System, collections::HashMap};
+
+#[global_allocator]
+static ALLOCATOR: HeapProfiler<System> = HeapProfiler::with_sample_interval(System, 64 * 1024);
+
+fn build_map() -> HashMap<u64, String> {
+    (0..100_000).map(|i| (i, format!("value {}", i))).collect()
+}
+
+fn build_vecs() -> Vec<Vec<u8>> {
+    (0..1000).map(|i| vec![0; i * 10]).collect()
+}
+
+fn main() {
+    let path = std::env::args().nth(1).unwrap_or("heap.pb".to_string());
+    let format = if path.ends_with(".folded") {
+        ProfileFormat::FoldedAllocated
+    } else {
+        ProfileFormat::Pprof
+    };
+    ALLOCATOR.write_profile_at_exit(Box::leak(path.into_boxed_str()), format);
+
+    let map = build_map();
+    let vecs = build_vecs();
+    println!("{} entries, {} vectors", map.len(), vecs.len());
+    std::mem::forget(map);
+}
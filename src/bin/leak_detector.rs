@@ -0,0 +1,33 @@
+//! Leaks a few blocks on purpose, and prints where they were allocated when
+//! the process exits.
+//!
+//! Frames are only named for exported symbols; build with
+//! `RUSTFLAGS="-C link-args=-rdynamic"` to export those of the binary too.
+use memory_allocator_performance_rs::LeakDetector;
+use std::alloc::System;
+
+#[global_allocator]
+static ALLOCATOR: LeakDetector<System> = LeakDetector::new(System);
+
+fn leak_strings() {
+    for i in 0..10 {
+        std::mem::forget(format!("leaked string {}", i));
+    }
+}
+
+fn leak_vec() {
+    let v: Vec<u64> = (0..1000).collect();
+    Box::leak(v.into_boxed_slice());
+}
+
+fn main() {
+    ALLOCATOR.report_at_exit();
+
+    let kept: Vec<String> = (0..100).map(|i| i.to_string()).collect();
+    drop(kept);
+    leak_strings();
+    leak_vec();
+
+    let (blocks, bytes) = ALLOCATOR.live();
+    println!("{} blocks, {} bytes live", blocks, bytes);
+}
@@ -0,0 +1,53 @@
+//! Logs through the `log` crate both from a global allocator and from the
+//! application, whose logger allocates. Run with `ALLOC_LOG=trace` to see the
+//! allocator's messages.
+use alloc_fmt::log_backend::{self, AllocLogger};
+use std::alloc::{GlobalAlloc, Layout, System};
+
+/// `System`, logging every allocation.
+struct LoggingAlloc;
+
+unsafe impl GlobalAlloc for LoggingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _allocator = log_backend::enter_allocator();
+        let ptr = System.alloc(layout);
+        log::trace!("allocating {} bytes at {:p}", layout.size(), ptr);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let _allocator = log_backend::enter_allocator();
+        log::trace!("deallocating {} bytes at {:p}", layout.size(), ptr);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: LoggingAlloc = LoggingAlloc;
+
+/// An ordinary logger, which allocates.
+struct AppLogger;
+
+impl log::Log for AppLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Info
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            let line = format!("app: [{}] {}", record.level(), record.args());
+            println!("{}", line);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static APP_LOGGER: AppLogger = AppLogger;
+static LOGGER: AllocLogger = AllocLogger::with_fallback(&APP_LOGGER);
+
+fn main() {
+    log_backend::init(&LOGGER).unwrap();
+    let words: Vec<String> = ["hello", "world"].iter().map(|w| w.to_string()).collect();
+    log::info!("built {} words", words.len());
+}
@@ -0,0 +1,22 @@
+//! Dumps allocation stats as JSON to stderr whenever the process receives
+//! `SIGUSR1`. Try `kill -USR1 <pid>` while it runs, or let it signal itself.
+use memory_allocator_performance_rs::{SbrkAlloc, StatsAlloc};
+use std::thread::sleep;
+use std::time::Duration;
+
+#[global_allocator]
+static ALLOCATOR: StatsAlloc<SbrkAlloc> = StatsAlloc::new(SbrkAlloc::new());
+
+fn main() {
+    ALLOCATOR.dump_on_signal(libc::STDERR_FILENO);
+    println!("pid {}", std::process::id());
+
+    let mut blocks = Vec::new();
+    for round in 0..5 {
+        blocks.extend((0..100).map(|i| vec![0u8; i * 64 * (round + 1)]));
+        unsafe { libc::raise(libc::SIGUSR1) };
+        sleep(Duration::from_millis(500));
+    }
+    drop(blocks);
+    ALLOCATOR.dump(libc::STDERR_FILENO);
+}
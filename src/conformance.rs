@@ -0,0 +1,449 @@
+//! A conformance suite checking that an allocator honours the `Allocator` and
+//! `GlobalAlloc` contracts.
+//!
+//! Every check panics with a description of the first violation it finds. The
+//! allocator under test is created anew for every check, so allocators that
+//! never reuse memory (arenas) only need to be sized for a single check.
+use std::{
+    alloc::{AllocError, Allocator, GlobalAlloc, Layout},
+    any::type_name,
+    ptr::NonNull,
+};
+
+/// Every allocator has to support alignments up to the page size.
+const PAGE_SIZE: usize = 4096;
+/// Larger alignments may be refused, but must be honoured when they are not.
+const MAX_ALIGN: usize = 1 << 20;
+const OVERLAP_BLOCKS: usize = 1000;
+/// Sizes visited by `grow` and then by `shrink`, in that order.
+const RESIZE_SIZES: [usize; 7] = [1, 16, 100, 4096, 100_000, 1 << 20, 3 << 20];
+const ZEROED_SIZES: [usize; 6] = [1, 16, 100, 4096, 100_000, 1 << 20];
+/// Sizes that allocators rounding up to size classes do not serve exactly.
+const LENGTH_SIZES: [usize; 6] = [1, 24, 100, 129, 3000, 100_000];
+/// Far more than any machine can provide, yet a valid layout.
+const HUGE_SIZE: usize = isize::MAX as usize / 2;
+
+/// Runs every check on allocators created by `make`.
+pub fn check<A: Allocator>(make: impl Fn() -> A) {
+    check_alignment(&make());
+    check_no_overlap(&make());
+    check_grow_and_shrink(&make());
+    check_zeroed(&make());
+    check_zero_sized(&make());
+    check_returned_length(&make());
+    check_out_of_memory(&make());
+}
+
+/// Checks that clones behave like the allocator they come from: blocks of one
+/// never overlap blocks of the other, and can be resized and freed by either.
+pub fn check_clones<A: Allocator + Clone>(make: impl Fn() -> A) {
+    let allocator = make();
+    let clone = allocator.clone();
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let mut blocks = Vec::new();
+    for i in 0..OVERLAP_BLOCKS {
+        let owner = if i % 2 == 0 { &allocator } else { &clone };
+        let block = owner
+            .allocate(layout)
+            .unwrap_or_else(|_| panic!("{}: allocation failed", type_name::<A>()));
+        unsafe { fill(block.cast(), layout.size(), i) };
+        blocks.push(block.cast::<u8>());
+    }
+    assert_disjoint::<A>(blocks.iter().map(|&ptr| (ptr, layout.size())).collect());
+
+    let grown = Layout::from_size_align(256, 8).unwrap();
+    for (i, ptr) in blocks.into_iter().enumerate() {
+        // Resize with the other handle than the one that allocated the block.
+        let other = if i % 2 == 0 { &clone } else { &allocator };
+        unsafe {
+            assert_filled::<A>(ptr, layout.size(), i, "after allocating from a clone");
+            let ptr = other
+                .grow(ptr, layout, grown)
+                .unwrap_or_else(|_| panic!("{}: grow failed", type_name::<A>()))
+                .cast();
+            assert_filled::<A>(ptr, layout.size(), i, "after growing through a clone");
+            other.deallocate(ptr, grown);
+        }
+    }
+}
+
+/// Runs the checks that apply to a global allocator. Zero-sized layouts are not
+/// checked since they are not allowed by `GlobalAlloc`.
+pub fn check_global<G: GlobalAlloc>(allocator: &G) {
+    let allocator = Global(allocator);
+    check_alignment(&allocator);
+    check_no_overlap(&allocator);
+    check_grow_and_shrink(&allocator);
+    check_zeroed(&allocator);
+    check_out_of_memory(&allocator);
+}
+
+/// Every power-of-two alignment, with sizes smaller than, equal to and larger
+/// than the alignment.
+fn check_alignment<A: Allocator>(allocator: &A) {
+    let mut align = 1;
+    while align <= MAX_ALIGN {
+        for size in [1, 24, align, align + 1] {
+            let layout = Layout::from_size_align(size, align).unwrap();
+            let Ok(block) = allocator.allocate(layout) else {
+                assert!(
+                    align > PAGE_SIZE,
+                    "{}: failed to allocate {:?}",
+                    type_name::<A>(),
+                    layout
+                );
+                continue;
+            };
+            assert_block::<A>(block, layout);
+            unsafe {
+                fill(block.cast(), size, align);
+                allocator.deallocate(block.cast(), layout);
+            }
+        }
+        align *= 2;
+    }
+}
+
+/// Live blocks of mixed sizes never overlap, and writing one never clobbers another.
+fn check_no_overlap<A: Allocator>(allocator: &A) {
+    let mut blocks = Vec::new();
+    for i in 0..OVERLAP_BLOCKS {
+        let size = 1 + (i * 37) % 4096;
+        let align = 1 << (i % 7);
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let block = allocator
+            .allocate(layout)
+            .unwrap_or_else(|_| panic!("{}: failed to allocate {:?}", type_name::<A>(), layout));
+        assert_block::<A>(block, layout);
+        unsafe { fill(block.cast(), size, i) };
+        blocks.push((block.cast::<u8>(), layout));
+        // Free a block now and then so that freed memory gets reused.
+        if i % 3 == 2 {
+            let (ptr, layout) = blocks.swap_remove(i % blocks.len());
+            unsafe { allocator.deallocate(ptr, layout) };
+        }
+    }
+
+    assert_disjoint::<A>(
+        blocks
+            .iter()
+            .map(|&(ptr, layout)| (ptr, layout.size()))
+            .collect(),
+    );
+    for (ptr, layout) in blocks {
+        unsafe {
+            let seed = ptr.as_ptr().read() as usize;
+            assert_filled::<A>(ptr, layout.size(), seed, "while other blocks were live");
+            allocator.deallocate(ptr, layout);
+        }
+    }
+}
+
+/// Contents survive `grow` and `shrink`, including when the alignment changes,
+/// and `grow_zeroed` zeroes the new part.
+fn check_grow_and_shrink<A: Allocator>(allocator: &A) {
+    for align in [1, 8, 64, PAGE_SIZE] {
+        for new_align in [align, 16] {
+            let mut layout = Layout::from_size_align(RESIZE_SIZES[0], align).unwrap();
+            let mut ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+            unsafe { fill(ptr, layout.size(), align) };
+            let grown = RESIZE_SIZES.iter().skip(1);
+            let shrunk = RESIZE_SIZES.iter().rev().skip(1);
+            for (step, &size) in grown.chain(shrunk).enumerate() {
+                let new_layout = Layout::from_size_align(size, new_align).unwrap();
+                let kept = layout.size().min(size);
+                unsafe {
+                    ptr = if size > layout.size() {
+                        if step % 2 == 0 {
+                            allocator.grow(ptr, layout, new_layout)
+                        } else {
+                            allocator.grow_zeroed(ptr, layout, new_layout)
+                        }
+                    } else {
+                        allocator.shrink(ptr, layout, new_layout)
+                    }
+                    .unwrap_or_else(|_| {
+                        panic!(
+                            "{}: failed to resize {:?} to {:?}",
+                            type_name::<A>(),
+                            layout,
+                            new_layout
+                        )
+                    })
+                    .cast();
+                    assert_eq!(
+                        ptr.as_ptr() as usize % new_align,
+                        0,
+                        "{}: resized block misaligned",
+                        type_name::<A>()
+                    );
+                    assert_filled::<A>(ptr, kept, align, "after resizing");
+                    if size > layout.size() && step % 2 == 1 {
+                        assert_zeroed::<A>(ptr.add(kept), size - kept, "by grow_zeroed");
+                    }
+                    fill(ptr, size, align);
+                }
+                layout = new_layout;
+            }
+            unsafe { allocator.deallocate(ptr, layout) };
+        }
+    }
+}
+
+/// `allocate_zeroed` returns zeroed memory, even when it reuses freed blocks
+/// that were dirtied.
+fn check_zeroed<A: Allocator>(allocator: &A) {
+    for round in 0..2 {
+        let blocks: Vec<_> = ZEROED_SIZES
+            .iter()
+            .map(|&size| {
+                let layout = Layout::from_size_align(size, 8).unwrap();
+                let block = allocator.allocate_zeroed(layout).unwrap_or_else(|_| {
+                    panic!("{}: failed to allocate {:?}", type_name::<A>(), layout)
+                });
+                assert_block::<A>(block, layout);
+                unsafe { assert_zeroed::<A>(block.cast(), size, "by allocate_zeroed") };
+                (block.cast::<u8>(), layout)
+            })
+            .collect();
+        for (ptr, layout) in blocks {
+            unsafe {
+                fill(ptr, layout.size(), round + 1);
+                allocator.deallocate(ptr, layout);
+            }
+        }
+    }
+}
+
+/// Zero-sized layouts must succeed with an aligned pointer, and be resizable.
+fn check_zero_sized<A: Allocator>(allocator: &A) {
+    let mut align = 1;
+    while align <= PAGE_SIZE {
+        let empty = Layout::from_size_align(0, align).unwrap();
+        let block = allocator
+            .allocate(empty)
+            .unwrap_or_else(|_| panic!("{}: failed to allocate {:?}", type_name::<A>(), empty));
+        assert_block::<A>(block, empty);
+        unsafe { allocator.deallocate(block.cast(), empty) };
+
+        let zeroed = allocator.allocate_zeroed(empty).unwrap();
+        assert_block::<A>(zeroed, empty);
+        let layout = Layout::from_size_align(64, align).unwrap();
+        unsafe {
+            let ptr = allocator.grow(zeroed.cast(), empty, layout).unwrap().cast();
+            fill(ptr, layout.size(), align);
+            let ptr = allocator.shrink(ptr, layout, empty).unwrap();
+            assert_block::<A>(ptr, empty);
+            allocator.deallocate(ptr.cast(), empty);
+        }
+        align *= 2;
+    }
+}
+
+/// A block may be resized and freed with the length returned for it, which can
+/// be longer than the size asked for.
+fn check_returned_length<A: Allocator>(allocator: &A) {
+    for size in LENGTH_SIZES {
+        let layout = Layout::from_size_align(size, 8).unwrap();
+        let block = allocator
+            .allocate(layout)
+            .unwrap_or_else(|_| panic!("{}: failed to allocate {:?}", type_name::<A>(), layout));
+        assert_block::<A>(block, layout);
+        unsafe {
+            let returned = Layout::from_size_align(block.len(), 8).unwrap();
+            fill(block.cast(), returned.size(), size);
+            allocator.deallocate(block.cast(), returned);
+
+            let block = allocator.allocate(layout).unwrap();
+            let returned = Layout::from_size_align(block.len(), 8).unwrap();
+            fill(block.cast(), returned.size(), size);
+            let grown = Layout::from_size_align(returned.size() * 2, 8).unwrap();
+            let block = allocator
+                .grow(block.cast(), returned, grown)
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "{}: failed to grow {:?} to {:?}",
+                        type_name::<A>(),
+                        returned,
+                        grown
+                    )
+                });
+            assert_block::<A>(block, grown);
+            assert_filled::<A>(block.cast(), returned.size(), size, "after growing");
+            let returned = Layout::from_size_align(block.len(), 8).unwrap();
+            let block = allocator
+                .shrink(block.cast(), returned, layout)
+                .unwrap_or_else(|_| {
+                    panic!(
+                        "{}: failed to shrink {:?} to {:?}",
+                        type_name::<A>(),
+                        returned,
+                        layout
+                    )
+                });
+            assert_block::<A>(block, layout);
+            assert_filled::<A>(block.cast(), size, size, "after shrinking");
+            let returned = Layout::from_size_align(block.len(), 8).unwrap();
+            allocator.deallocate(block.cast(), returned);
+        }
+    }
+}
+
+/// An impossible request fails cleanly, and leaves the allocator usable.
+fn check_out_of_memory<A: Allocator>(allocator: &A) {
+    let huge = Layout::from_size_align(HUGE_SIZE, 8).unwrap();
+    assert!(
+        allocator.allocate(huge).is_err(),
+        "{}: allocating {} bytes succeeded",
+        type_name::<A>(),
+        HUGE_SIZE
+    );
+    assert!(allocator.allocate_zeroed(huge).is_err());
+
+    let layout = Layout::from_size_align(64, 8).unwrap();
+    let ptr = allocator.allocate(layout).unwrap().cast();
+    unsafe {
+        fill(ptr, layout.size(), 0);
+        assert!(
+            allocator.grow(ptr, layout, huge).is_err(),
+            "{}: growing to {} bytes succeeded",
+            type_name::<A>(),
+            HUGE_SIZE
+        );
+        // A failed grow leaves the block untouched.
+        assert_filled::<A>(ptr, layout.size(), 0, "after a failed grow");
+        allocator.deallocate(ptr, layout);
+    }
+
+    let ptr = allocator.allocate(layout).unwrap();
+    unsafe { allocator.deallocate(ptr.cast(), layout) };
+}
+
+fn assert_block<A>(block: NonNull<[u8]>, layout: Layout) {
+    assert!(
+        block.len() >= layout.size(),
+        "{}: block of {} bytes returned for {:?}",
+        type_name::<A>(),
+        block.len(),
+        layout
+    );
+    assert_eq!(
+        block.cast::<u8>().as_ptr() as usize % layout.align(),
+        0,
+        "{}: misaligned block returned for {:?}",
+        type_name::<A>(),
+        layout
+    );
+}
+
+fn assert_disjoint<A>(mut blocks: Vec<(NonNull<u8>, usize)>) {
+    blocks.sort_by_key(|&(ptr, _)| ptr);
+    for pair in blocks.windows(2) {
+        let ((first, size), (second, _)) = (pair[0], pair[1]);
+        assert!(
+            first.as_ptr() as usize + size <= second.as_ptr() as usize,
+            "{}: blocks at {:p} ({} bytes) and {:p} overlap",
+            type_name::<A>(),
+            first,
+            size,
+            second
+        );
+    }
+}
+
+/// Writes a pattern derived from `seed`, whose first byte is the seed itself.
+unsafe fn fill(ptr: NonNull<u8>, size: usize, seed: usize) {
+    for i in 0..size {
+        ptr.add(i).write(pattern(seed, i));
+    }
+}
+
+fn pattern(seed: usize, i: usize) -> u8 {
+    (seed as u8).wrapping_add((i as u8).wrapping_mul(31))
+}
+
+unsafe fn assert_filled<A>(ptr: NonNull<u8>, size: usize, seed: usize, context: &str) {
+    for i in 0..size {
+        assert_eq!(
+            ptr.add(i).read(),
+            pattern(seed, i),
+            "{}: byte {} of the block at {:p} changed {}",
+            type_name::<A>(),
+            i,
+            ptr,
+            context
+        );
+    }
+}
+
+unsafe fn assert_zeroed<A>(ptr: NonNull<u8>, size: usize, context: &str) {
+    for i in 0..size {
+        assert_eq!(
+            ptr.add(i).read(),
+            0,
+            "{}: byte {} of the block at {:p} not zeroed {}",
+            type_name::<A>(),
+            i,
+            ptr,
+            context
+        );
+    }
+}
+
+/// Exposes a `GlobalAlloc` as an `Allocator`, resizing with `realloc` whenever
+/// the alignment allows it.
+struct Global<'a, G>(&'a G);
+
+unsafe impl<G: GlobalAlloc> Allocator for Global<'_, G> {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.0.alloc(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    fn allocate_zeroed(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = NonNull::new(unsafe { self.0.alloc_zeroed(layout) }).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        self.0.dealloc(ptr.as_ptr(), layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+
+    unsafe fn shrink(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        self.resize(ptr, old_layout, new_layout)
+    }
+}
+
+impl<G: GlobalAlloc> Global<'_, G> {
+    unsafe fn resize(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        if old_layout.align() != new_layout.align() {
+            let new_ptr = self.allocate(new_layout)?;
+            let size = old_layout.size().min(new_layout.size());
+            std::ptr::copy_nonoverlapping(ptr.as_ptr(), new_ptr.cast::<u8>().as_ptr(), size);
+            self.deallocate(ptr, old_layout);
+            return Ok(new_ptr);
+        }
+        let new_ptr = self.0.realloc(ptr.as_ptr(), old_layout, new_layout.size());
+        let new_ptr = NonNull::new(new_ptr).ok_or(AllocError)?;
+        Ok(NonNull::slice_from_raw_parts(new_ptr, new_layout.size()))
+    }
+}
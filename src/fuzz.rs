@@ -0,0 +1,352 @@
+//! Model-based randomized testing of allocators.
+//!
+//! A sequence of operations is run against an allocator while a shadow model
+//! tracks the live blocks and the pattern each of them is filled with. Every
+//! step checks the new block for misalignment and overlap with live blocks, and
+//! the blocks it touches for corruption. A failing sequence is then shrunk to
+//! the shortest one that still fails.
+//!
+//! Allocators may refuse a request, for instance once an arena is exhausted: the
+//! operation is then skipped, and a block that failed to resize is checked to be
+//! left as it was.
+//!
+//! Sequences are generated from a seed with `generate`, or decoded from raw
+//! bytes with `decode` for coverage-guided fuzzers.
+use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
+use std::{
+    alloc::{Allocator, Layout},
+    any::type_name,
+    fmt,
+    ops::Range,
+    ptr::NonNull,
+};
+
+const MAX_ALIGN_SHIFT: u32 = 12;
+
+/// One step of a sequence. `index` designates a live block, modulo the number of
+/// live blocks; operations on a block are skipped while there is none.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Op {
+    Allocate {
+        size: usize,
+        align: usize,
+    },
+    AllocateZeroed {
+        size: usize,
+        align: usize,
+    },
+    Deallocate {
+        index: usize,
+    },
+    /// Grows the block by `by` bytes.
+    Grow {
+        index: usize,
+        by: usize,
+        align: usize,
+    },
+    /// Shrinks the block to at most `to` bytes.
+    Shrink {
+        index: usize,
+        to: usize,
+        align: usize,
+    },
+}
+
+/// The first check that failed, at `step` in the sequence.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Failure {
+    pub step: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "step {}: {}", self.step, self.message)
+    }
+}
+
+struct Block {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    seed: u8,
+}
+
+/// Mostly small sizes, some medium ones and a few large ones.
+fn random_size(rng: &mut ChaCha8Rng) -> usize {
+    match rng.gen_range(0..100) {
+        0..=1 => 0,
+        2..=79 => rng.gen_range(1..=256),
+        80..=97 => rng.gen_range(257..=16 * 1024),
+        _ => rng.gen_range(16 * 1024..=1024 * 1024),
+    }
+}
+
+/// Mostly the natural alignments, sometimes up to a page.
+fn random_align(rng: &mut ChaCha8Rng) -> usize {
+    if rng.gen_bool(0.9) {
+        1 << rng.gen_range(0..=4)
+    } else {
+        1 << rng.gen_range(5..=MAX_ALIGN_SHIFT)
+    }
+}
+
+/// Generates `len` operations from `seed`.
+pub fn generate(seed: u64, len: usize) -> Vec<Op> {
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    (0..len)
+        .map(|_| {
+            let index = rng.gen();
+            match rng.gen_range(0..10) {
+                0..=2 => Op::Allocate {
+                    size: random_size(&mut rng),
+                    align: random_align(&mut rng),
+                },
+                3 => Op::AllocateZeroed {
+                    size: random_size(&mut rng),
+                    align: random_align(&mut rng),
+                },
+                4..=6 => Op::Deallocate { index },
+                7..=8 => Op::Grow {
+                    index,
+                    by: random_size(&mut rng),
+                    align: random_align(&mut rng),
+                },
+                _ => Op::Shrink {
+                    index,
+                    to: random_size(&mut rng),
+                    align: random_align(&mut rng),
+                },
+            }
+        })
+        .collect()
+}
+
+/// Decodes operations from arbitrary bytes, five bytes per operation: the kind,
+/// the block index, the size on two bytes and the alignment.
+pub fn decode(data: &[u8]) -> Vec<Op> {
+    data.chunks_exact(5)
+        .map(|chunk| {
+            let index = chunk[1] as usize;
+            let size = u16::from_le_bytes([chunk[2], chunk[3]]) as usize;
+            let align = 1 << (chunk[4] as u32 % (MAX_ALIGN_SHIFT + 1));
+            match chunk[0] % 5 {
+                0 => Op::Allocate { size, align },
+                1 => Op::AllocateZeroed { size, align },
+                2 => Op::Deallocate { index },
+                3 => Op::Grow {
+                    index,
+                    by: size,
+                    align,
+                },
+                _ => Op::Shrink {
+                    index,
+                    to: size,
+                    align,
+                },
+            }
+        })
+        .collect()
+}
+
+/// Runs `ops` against `allocator`, freeing every block left at the end.
+///
+/// On failure, the live blocks are leaked since the allocator can no longer be
+/// trusted with them.
+pub fn run<A: Allocator>(allocator: &A, ops: &[Op]) -> Result<(), Failure> {
+    let mut blocks: Vec<Block> = Vec::new();
+    for (step, &op) in ops.iter().enumerate() {
+        let fail = |message: String| Failure { step, message };
+        match op {
+            Op::Allocate { size, align } | Op::AllocateZeroed { size, align } => {
+                let layout = Layout::from_size_align(size, align).unwrap();
+                let zeroed = matches!(op, Op::AllocateZeroed { .. });
+                let result = if zeroed {
+                    allocator.allocate_zeroed(layout)
+                } else {
+                    allocator.allocate(layout)
+                };
+                let Ok(ptr) = result else {
+                    continue;
+                };
+                let ptr = ptr.cast();
+                check_new_block(&blocks, ptr, layout).map_err(fail)?;
+                if zeroed {
+                    check_zeroed(ptr, size).map_err(fail)?;
+                }
+                let seed = step as u8;
+                unsafe { fill(ptr, 0, size, seed) };
+                blocks.push(Block { ptr, layout, seed });
+            }
+            Op::Deallocate { index } => {
+                if blocks.is_empty() {
+                    continue;
+                }
+                let block = blocks.swap_remove(index % blocks.len());
+                check_pattern(&block, block.layout.size()).map_err(fail)?;
+                unsafe { allocator.deallocate(block.ptr, block.layout) };
+            }
+            Op::Grow { index, .. } | Op::Shrink { index, .. } => {
+                if blocks.is_empty() {
+                    continue;
+                }
+                let block = blocks.swap_remove(index % blocks.len());
+                check_pattern(&block, block.layout.size()).map_err(fail)?;
+                let old_size = block.layout.size();
+                let grow = matches!(op, Op::Grow { .. });
+                let (new_size, align) = match op {
+                    Op::Grow { by, align, .. } => (old_size.saturating_add(by), align),
+                    Op::Shrink { to, align, .. } => (old_size.min(to), align),
+                    _ => unreachable!(),
+                };
+                let new_layout = Layout::from_size_align(new_size, align)
+                    .map_err(|_| fail(format!("invalid layout of {} bytes", new_size)))?;
+                let result = unsafe {
+                    if grow {
+                        allocator.grow(block.ptr, block.layout, new_layout)
+                    } else {
+                        allocator.shrink(block.ptr, block.layout, new_layout)
+                    }
+                };
+                let Ok(ptr) = result else {
+                    check_pattern(&block, old_size)
+                        .map_err(|message| fail(format!("{} after a failed resize", message)))?;
+                    blocks.push(block);
+                    continue;
+                };
+                let ptr = ptr.cast();
+                check_new_block(&blocks, ptr, new_layout).map_err(fail)?;
+                let block = Block {
+                    ptr,
+                    layout: new_layout,
+                    seed: block.seed,
+                };
+                check_pattern(&block, old_size.min(new_size))
+                    .map_err(|message| fail(format!("{} after resizing", message)))?;
+                unsafe { fill(ptr, old_size, new_size, block.seed) };
+                blocks.push(block);
+            }
+        }
+    }
+
+    for block in blocks {
+        check_pattern(&block, block.layout.size()).map_err(|message| Failure {
+            step: ops.len(),
+            message,
+        })?;
+        unsafe { allocator.deallocate(block.ptr, block.layout) };
+    }
+    Ok(())
+}
+
+/// Shrinks a failing sequence by removing ever smaller chunks of operations, as
+/// long as the remaining sequence still fails on an allocator from `make`.
+pub fn minimize<A: Allocator>(make: impl Fn() -> A, mut ops: Vec<Op>) -> Vec<Op> {
+    let mut chunk = ops.len().div_ceil(2);
+    while chunk > 0 {
+        let mut start = 0;
+        while start < ops.len() {
+            let end = (start + chunk).min(ops.len());
+            let candidate: Vec<Op> = ops[..start].iter().chain(&ops[end..]).copied().collect();
+            if run(&make(), &candidate).is_err() {
+                ops = candidate;
+            } else {
+                start = end;
+            }
+        }
+        chunk /= 2;
+    }
+    ops
+}
+
+/// Runs `len` generated operations for every seed in `seeds` against allocators
+/// from `make`. Panics with the shortest failing sequence found.
+pub fn check<A: Allocator>(make: impl Fn() -> A, seeds: Range<u64>, len: usize) {
+    for seed in seeds {
+        check_ops(&make, generate(seed, len), &format!("seed {}", seed));
+    }
+}
+
+/// Runs the operations decoded from `data`, for coverage-guided fuzzers.
+pub fn check_bytes<A: Allocator>(make: impl Fn() -> A, data: &[u8]) {
+    check_ops(&make, decode(data), "fuzzer input");
+}
+
+fn check_ops<A: Allocator>(make: &impl Fn() -> A, ops: Vec<Op>, origin: &str) {
+    let Err(failure) = run(&make(), &ops) else {
+        return;
+    };
+    let shortest = minimize(make, ops);
+    let failure = run(&make(), &shortest).err().unwrap_or(failure);
+    let mut message = format!(
+        "{}: {} failed at {}\nshortest failing sequence ({} operations):\n",
+        type_name::<A>(),
+        origin,
+        failure,
+        shortest.len()
+    );
+    for op in &shortest {
+        message.push_str(&format!("    {:?}\n", op));
+    }
+    panic!("{}", message);
+}
+
+fn check_new_block(blocks: &[Block], ptr: NonNull<u8>, layout: Layout) -> Result<(), String> {
+    let start = ptr.as_ptr() as usize;
+    if !start.is_multiple_of(layout.align()) {
+        return Err(format!("block at {:p} misaligned for {:?}", ptr, layout));
+    }
+    if layout.size() == 0 {
+        return Ok(());
+    }
+    let end = start + layout.size();
+    for block in blocks {
+        let block_start = block.ptr.as_ptr() as usize;
+        let block_end = block_start + block.layout.size();
+        if block.layout.size() != 0 && start < block_end && block_start < end {
+            return Err(format!(
+                "block at {:p} ({} bytes) overlaps live block at {:p} ({} bytes)",
+                ptr,
+                layout.size(),
+                block.ptr,
+                block.layout.size()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn pattern(seed: u8, i: usize) -> u8 {
+    seed.wrapping_add((i as u8).wrapping_mul(31))
+}
+
+unsafe fn fill(ptr: NonNull<u8>, from: usize, to: usize, seed: u8) {
+    for i in from..to {
+        ptr.add(i).write(pattern(seed, i));
+    }
+}
+
+/// Checks the first `size` bytes of `block`.
+fn check_pattern(block: &Block, size: usize) -> Result<(), String> {
+    for i in 0..size {
+        let byte = unsafe { block.ptr.add(i).read() };
+        if byte != pattern(block.seed, i) {
+            return Err(format!(
+                "byte {} of the block at {:p} ({} bytes) corrupted",
+                i,
+                block.ptr,
+                block.layout.size()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn check_zeroed(ptr: NonNull<u8>, size: usize) -> Result<(), String> {
+    for i in 0..size {
+        if unsafe { ptr.add(i).read() } != 0 {
+            return Err(format!("byte {} of the block at {:p} not zeroed", i, ptr));
+        }
+    }
+    Ok(())
+}
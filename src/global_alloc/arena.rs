@@ -3,7 +3,10 @@ use std::cell::UnsafeCell;
 use std::ptr::null_mut;
 use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 
-use alloc_fmt::alloc_eprintln;
+use alloc_fmt::alloc_trace;
+use alloc_fmt::record::JsonObject;
+
+use crate::global_alloc::stats::AllocatorStats;
 
 const ARENA_SIZE: usize = 128 * 1024 * 1024;
 
@@ -28,19 +31,29 @@ impl SimpleAlloc {
 
 unsafe impl Sync for SimpleAlloc {}
 
+impl AllocatorStats for SimpleAlloc {
+    fn write_stats(&self, stats: &mut JsonObject) {
+        stats
+            .field("arena_offset", self.offset.load(Relaxed))
+            .field("arena_size", ARENA_SIZE);
+    }
+}
+
 unsafe impl GlobalAlloc for SimpleAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
         let align = layout.align();
         let offset = self.offset.load(Relaxed);
-        let ptr = align_up(offset, align);
+        // Align the address rather than the offset, the arena itself being byte-aligned.
+        let base = self.arena.get() as usize;
+        let ptr = align_up(base + offset, align) - base;
         let new_offset = ptr + size;
         if new_offset > ARENA_SIZE {
             return null_mut();
         }
         self.offset.store(new_offset, Relaxed);
-        let ptr = self.arena.get().cast::<u8>().add(offset);
-        alloc_eprintln!(
+        let ptr = self.arena.get().cast::<u8>().add(ptr);
+        alloc_trace!(
             "allocating {:?} bytes at {:p} (align: {:?})",
             size,
             ptr,
@@ -50,6 +63,6 @@ unsafe impl GlobalAlloc for SimpleAlloc {
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        alloc_eprintln!("deallocating {:p}", ptr);
+        alloc_trace!("deallocating {:p}", ptr);
     }
 }
@@ -0,0 +1,361 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::cell::Cell;
+use std::fmt::{Display, Write};
+use std::io;
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+use alloc_fmt::alloc_eprintln;
+use alloc_fmt::backtrace::{self, FunctionName, MAX_FRAMES};
+
+use crate::global_alloc::profile_writer::{message, packed, string, uint, FileWriter, Sink};
+use crate::global_alloc::site_table::{guarded, Site, Slot, Tables};
+
+/// Mean number of bytes between samples, as in Go's runtime.
+const DEFAULT_SAMPLE_INTERVAL: usize = 512 * 1024;
+/// Slots for live sampled blocks. Samples beyond three quarters of that are dropped.
+const MAX_SAMPLES: usize = 1 << 16;
+/// Slots for allocation sites.
+const MAX_SITES: usize = 4096;
+
+/// Output format of `HeapProfiler::write_profile`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProfileFormat {
+    /// A pprof protobuf profile, with the allocated and in-use objects and bytes
+    /// of every stack. `go tool pprof` reads it uncompressed.
+    Pprof,
+    /// Folded stacks weighted by the bytes in use, one stack per line, for
+    /// `flamegraph.pl` or `inferno-flamegraph`.
+    FoldedInUse,
+    /// Folded stacks weighted by the bytes allocated since the start.
+    FoldedAllocated,
+}
+
+/// Bytes left before the next sample on this thread, and the state of its
+/// random generator, zero until seeded.
+struct Sampler {
+    until_next: Cell<usize>,
+    rng: Cell<u64>,
+}
+
+thread_local! {
+    static SAMPLER: Sampler = const {
+        Sampler {
+            until_next: Cell::new(0),
+            rng: Cell::new(0),
+        }
+    };
+}
+
+impl Sampler {
+    /// Draws the distance to the next sample from an exponential distribution
+    /// of mean `interval`, making the sampled bytes a Poisson process.
+    fn next_interval(&self, interval: usize) -> usize {
+        let mut x = self.rng.get();
+        if x == 0 {
+            // Seed from the address of this thread's sampler.
+            x = (self as *const Sampler as u64 ^ 0x9e37_79b9_7f4a_7c15) | 1;
+        }
+        // xorshift64*
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng.set(x);
+        let uniform = (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64;
+        (-(1.0 - uniform).ln() * interval as f64) as usize + 1
+    }
+}
+
+/// A sampling heap profiler in the style of heaptrack or Go's pprof heap
+/// profiles, as a `GlobalAlloc` wrapper.
+///
+/// About one allocation every `sample_interval` bytes (512 KiB by default) is
+/// sampled, with its stack. Each stack keeps the samples allocated since the
+/// start and those still in use, which are scaled back to estimates of all the
+/// allocations when writing a profile.
+///
+/// Like `LeakDetector`, it keeps fixed-size side tables and is meant to live in
+/// a `static`.
+pub struct HeapProfiler<A: GlobalAlloc> {
+    inner: A,
+    sample_interval: usize,
+    tables: spin::Mutex<Tables<MAX_SAMPLES, MAX_SITES>>,
+    /// Live sampled blocks, so that freeing skips the lock while there is none.
+    sampled: AtomicUsize,
+}
+
+static AT_EXIT_REGISTERED: AtomicBool = AtomicBool::new(false);
+static AT_EXIT_PROFILER: AtomicPtr<()> = AtomicPtr::new(null_mut());
+/// `write_erased` for the type of `AT_EXIT_PROFILER`.
+static AT_EXIT_WRITE: AtomicPtr<()> = AtomicPtr::new(null_mut());
+static AT_EXIT_OUTPUT: spin::Mutex<Option<(&'static str, ProfileFormat)>> = spin::Mutex::new(None);
+
+unsafe fn write_erased<A: GlobalAlloc>(profiler: *const ()) {
+    let Some((path, format)) = *AT_EXIT_OUTPUT.lock() else {
+        return;
+    };
+    let profiler = &*profiler.cast::<HeapProfiler<A>>();
+    if let Err(error) = profiler.write_profile(path, format) {
+        alloc_eprintln!(
+            "HeapProfiler: could not write {} (os error {})",
+            path,
+            error.raw_os_error().unwrap_or(0)
+        );
+    }
+}
+
+extern "C" fn write_at_exit() {
+    let write = AT_EXIT_WRITE.load(Ordering::Relaxed);
+    unsafe {
+        let write = std::mem::transmute::<*mut (), unsafe fn(*const ())>(write);
+        write(AT_EXIT_PROFILER.load(Ordering::Relaxed));
+    }
+}
+
+impl<A: GlobalAlloc> HeapProfiler<A> {
+    pub const fn new(inner: A) -> Self {
+        Self::with_sample_interval(inner, DEFAULT_SAMPLE_INTERVAL)
+    }
+
+    /// Samples about once every `bytes` bytes allocated. 1 samples every allocation.
+    pub const fn with_sample_interval(inner: A, bytes: usize) -> Self {
+        HeapProfiler {
+            inner,
+            sample_interval: if bytes == 0 { 1 } else { bytes },
+            tables: spin::Mutex::new(Tables::new()),
+            sampled: AtomicUsize::new(0),
+        }
+    }
+
+    /// Writes a profile to `path` when the process exits. Only the last
+    /// profiler registered this way writes.
+    pub fn write_profile_at_exit(&'static self, path: &'static str, format: ProfileFormat) {
+        *AT_EXIT_OUTPUT.lock() = Some((path, format));
+        AT_EXIT_PROFILER.store(self as *const Self as *mut (), Ordering::Relaxed);
+        AT_EXIT_WRITE.store(write_erased::<A> as *mut (), Ordering::Relaxed);
+        if !AT_EXIT_REGISTERED.swap(true, Ordering::Relaxed) {
+            unsafe { libc::atexit(write_at_exit) };
+        }
+    }
+
+    /// Writes a profile of the allocations so far to `path`.
+    ///
+    /// Allocations from other threads wait while the profile is written.
+    pub fn write_profile(&self, path: &str, format: ProfileFormat) -> io::Result<()> {
+        guarded(|| {
+            let mut file = FileWriter::create(path)?;
+            let tables = self.tables.lock();
+            match format {
+                ProfileFormat::Pprof => self.write_pprof(&mut file, &tables.sites),
+                ProfileFormat::FoldedInUse => self.write_folded(&mut file, &tables.sites, |site| {
+                    self.estimate(site.live_blocks, site.live_bytes).1
+                }),
+                ProfileFormat::FoldedAllocated => {
+                    self.write_folded(&mut file, &tables.sites, |site| {
+                        self.estimate(site.total_blocks, site.total_bytes).1
+                    })
+                }
+            }
+            drop(tables);
+            file.finish()
+        })
+        // Writing a profile from the allocator itself, which would deadlock.
+        .unwrap_or_else(|| Err(io::ErrorKind::WouldBlock.into()))
+    }
+
+    /// Estimated objects and bytes from the sampled ones: a block of `size`
+    /// bytes is sampled with probability `1 - exp(-size / sample_interval)`.
+    fn estimate(&self, blocks: usize, bytes: usize) -> (u64, u64) {
+        if blocks == 0 || self.sample_interval == 1 {
+            return (blocks as u64, bytes as u64);
+        }
+        let average = bytes as f64 / blocks as f64;
+        let scale = 1.0 / (1.0 - (-average / self.sample_interval as f64).exp());
+        (
+            (blocks as f64 * scale) as u64,
+            (bytes as f64 * scale) as u64,
+        )
+    }
+
+    fn write_folded(&self, file: &mut FileWriter, sites: &[Site], value: impl Fn(&Site) -> u64) {
+        for site in sites.iter().filter(|site| site.is_used()) {
+            let value = value(site);
+            if value == 0 {
+                continue;
+            }
+            // Outermost frame first.
+            for (index, &ip) in site.frames.as_slice().iter().rev().enumerate() {
+                let separator = if index == 0 { "" } else { ";" };
+                let _ = write!(file, "{}{}", separator, FunctionName(ip));
+            }
+            let _ = writeln!(file, " {}", value);
+        }
+    }
+
+    /// Writes a `perftools.profiles.Profile` message. Strings are appended to
+    /// the string table as they are needed, and every frame of every site gets
+    /// its own location and function, which pprof merges by name.
+    fn write_pprof(&self, sink: &mut dyn Sink, sites: &[Site]) {
+        const SAMPLE_TYPE: u64 = 1;
+        const SAMPLE: u64 = 2;
+        const LOCATION: u64 = 4;
+        const FUNCTION: u64 = 5;
+        const STRING_TABLE: u64 = 6;
+        const PERIOD_TYPE: u64 = 11;
+        const PERIOD: u64 = 12;
+        const DEFAULT_SAMPLE_TYPE: u64 = 14;
+
+        // Index of the next string of the table.
+        let mut strings = 0;
+        let mut intern = |sink: &mut dyn Sink, value: &dyn Display| {
+            string(sink, STRING_TABLE, value);
+            strings += 1;
+            strings - 1
+        };
+        intern(sink, &"");
+
+        let value_type = |sink: &mut dyn Sink, field, kind: u64, unit: u64| {
+            message(sink, field, |sink| {
+                uint(sink, 1, kind);
+                uint(sink, 2, unit);
+            });
+        };
+        let count = intern(sink, &"count");
+        let bytes = intern(sink, &"bytes");
+        let mut kind = 0;
+        for name in [
+            "alloc_objects",
+            "alloc_space",
+            "inuse_objects",
+            "inuse_space",
+        ] {
+            kind = intern(sink, &name);
+            let unit = if name.ends_with("objects") {
+                count
+            } else {
+                bytes
+            };
+            value_type(sink, SAMPLE_TYPE, kind, unit);
+        }
+        // The last one, inuse_space.
+        uint(sink, DEFAULT_SAMPLE_TYPE, kind);
+        let space = intern(sink, &"space");
+        value_type(sink, PERIOD_TYPE, space, bytes);
+        uint(sink, PERIOD, self.sample_interval as u64);
+
+        for (index, site) in sites.iter().enumerate().filter(|(_, site)| site.is_used()) {
+            let frames = site.frames.as_slice();
+            let first_id = (index * MAX_FRAMES) as u64 + 1;
+            for (frame, &ip) in frames.iter().enumerate() {
+                let id = first_id + frame as u64;
+                let name = intern(sink, &FunctionName(ip));
+                message(sink, FUNCTION, |sink| {
+                    uint(sink, 1, id);
+                    uint(sink, 2, name);
+                    uint(sink, 3, name);
+                });
+                message(sink, LOCATION, |sink| {
+                    uint(sink, 1, id);
+                    // Inside the call instruction, like pprof expects.
+                    uint(sink, 3, ip as u64 - 1);
+                    message(sink, 4, |sink| uint(sink, 1, id));
+                });
+            }
+
+            let (alloc_objects, alloc_space) = self.estimate(site.total_blocks, site.total_bytes);
+            let (inuse_objects, inuse_space) = self.estimate(site.live_blocks, site.live_bytes);
+            let values = [alloc_objects, alloc_space, inuse_objects, inuse_space];
+            message(sink, SAMPLE, |sink| {
+                packed(sink, 1, first_id..first_id + frames.len() as u64);
+                packed(sink, 2, values.iter().copied());
+            });
+        }
+    }
+
+    /// Whether to sample an allocation of `size` bytes on this thread.
+    fn should_sample(&self, size: usize) -> bool {
+        if self.sample_interval == 1 {
+            return true;
+        }
+        if size == 0 {
+            return false;
+        }
+        SAMPLER
+            .try_with(|sampler| {
+                let mut until_next = sampler.until_next.get();
+                if sampler.rng.get() == 0 {
+                    until_next = sampler.next_interval(self.sample_interval);
+                }
+                if size < until_next {
+                    sampler.until_next.set(until_next - size);
+                    return false;
+                }
+                sampler
+                    .until_next
+                    .set(sampler.next_interval(self.sample_interval));
+                true
+            })
+            .unwrap_or(false)
+    }
+
+    #[inline(never)]
+    fn sample(&self, ptr: *mut u8, size: usize) {
+        if ptr.is_null() || !self.should_sample(size) {
+            return;
+        }
+        guarded(|| {
+            // Leave out this function.
+            let frames = backtrace::capture(1);
+            let mut tables = self.tables.lock();
+            tables.record(ptr as usize, size, &frames);
+            self.sampled.store(tables.live, Ordering::Release);
+        });
+    }
+
+    fn untrack(&self, ptr: *mut u8) -> Option<Slot> {
+        if self.sampled.load(Ordering::Acquire) == 0 {
+            return None;
+        }
+        let mut tables = self.tables.lock();
+        let removed = tables.remove(ptr as usize);
+        self.sampled.store(tables.live, Ordering::Release);
+        removed
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for HeapProfiler<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        self.sample(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        self.sample(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Forget the block first, since its address can be reused as soon as it is freed.
+        self.untrack(ptr);
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let old = self.untrack(ptr);
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            // The block is still there: put it back.
+            if let Some(old) = old {
+                let mut tables = self.tables.lock();
+                tables.insert(old);
+                self.sampled.store(tables.live, Ordering::Release);
+            }
+        } else {
+            // Counted as a new allocation, like a free followed by a malloc.
+            self.sample(new_ptr, new_size);
+        }
+        new_ptr
+    }
+}
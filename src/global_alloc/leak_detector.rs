@@ -0,0 +1,166 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr::null_mut;
+use std::sync::atomic::{AtomicBool, AtomicPtr, Ordering::Relaxed};
+
+use alloc_fmt::alloc_eprintln;
+use alloc_fmt::backtrace;
+
+use crate::global_alloc::site_table::{guarded, Slot, Tables};
+
+/// Slots for live allocations. Allocations beyond three quarters of that are only counted.
+const MAX_ALLOCATIONS: usize = 1 << 18;
+/// Slots for allocation sites.
+const MAX_SITES: usize = 4096;
+/// Sites printed in the report, the ones leaking the most bytes first.
+const REPORT_SITES: usize = 20;
+
+/// A `GlobalAlloc` wrapper reporting the blocks still allocated, grouped by the
+/// stack that allocated them.
+///
+/// Live blocks and their allocation stacks are kept in fixed-size side tables,
+/// which makes this type large: it is meant to live in a `static`. Call
+/// `report_at_exit` early in `main` to get a report when the process exits, or
+/// `report` at any time.
+pub struct LeakDetector<A: GlobalAlloc> {
+    inner: A,
+    tables: spin::Mutex<Tables<MAX_ALLOCATIONS, MAX_SITES>>,
+}
+
+static AT_EXIT_REGISTERED: AtomicBool = AtomicBool::new(false);
+static AT_EXIT_DETECTOR: AtomicPtr<()> = AtomicPtr::new(null_mut());
+/// `report_erased` for the type of `AT_EXIT_DETECTOR`.
+static AT_EXIT_REPORT: AtomicPtr<()> = AtomicPtr::new(null_mut());
+
+unsafe fn report_erased<A: GlobalAlloc>(detector: *const ()) {
+    (*detector.cast::<LeakDetector<A>>()).report()
+}
+
+extern "C" fn report_at_exit() {
+    let report = AT_EXIT_REPORT.load(Relaxed);
+    unsafe {
+        let report = std::mem::transmute::<*mut (), unsafe fn(*const ())>(report);
+        report(AT_EXIT_DETECTOR.load(Relaxed));
+    }
+}
+
+impl<A: GlobalAlloc> LeakDetector<A> {
+    pub const fn new(inner: A) -> Self {
+        LeakDetector {
+            inner,
+            tables: spin::Mutex::new(Tables::new()),
+        }
+    }
+
+    /// Prints the report when the process exits. Only the last detector
+    /// registered this way reports.
+    pub fn report_at_exit(&'static self) {
+        AT_EXIT_DETECTOR.store(self as *const Self as *mut (), Relaxed);
+        AT_EXIT_REPORT.store(report_erased::<A> as *mut (), Relaxed);
+        if !AT_EXIT_REGISTERED.swap(true, Relaxed) {
+            unsafe { libc::atexit(report_at_exit) };
+        }
+    }
+
+    /// Number of blocks and bytes currently allocated, among the tracked ones.
+    pub fn live(&self) -> (usize, usize) {
+        let tables = self.tables.lock();
+        (tables.live, tables.live_bytes())
+    }
+
+    /// Prints the blocks still allocated to stderr, grouped by allocation site.
+    pub fn report(&self) {
+        guarded(|| {
+            let tables = self.tables.lock();
+            let bytes = tables.live_bytes();
+            alloc_eprintln!(
+                "==leaks== {} bytes in {} blocks still allocated",
+                bytes,
+                tables.live
+            );
+            if tables.untracked > 0 {
+                alloc_eprintln!(
+                    "==leaks== {} more allocations were not tracked, the table being full",
+                    tables.untracked
+                );
+            }
+
+            // Repeatedly pick the biggest site not printed yet.
+            let mut printed = [usize::MAX; REPORT_SITES];
+            let mut printed_bytes = 0;
+            for rank in 0..REPORT_SITES {
+                let biggest = (0..MAX_SITES)
+                    .filter(|index| !printed[..rank].contains(index))
+                    .filter(|&index| tables.sites[index].live_blocks > 0)
+                    .max_by_key(|&index| tables.sites[index].live_bytes);
+                let Some(index) = biggest else {
+                    break;
+                };
+                printed[rank] = index;
+                let site = &tables.sites[index];
+                printed_bytes += site.live_bytes;
+                alloc_eprintln!(
+                    "==leaks== {} bytes in {} blocks allocated at:",
+                    site.live_bytes,
+                    site.live_blocks
+                );
+                backtrace::print(site.frames.as_slice(), "    ");
+            }
+            if printed_bytes < bytes {
+                alloc_eprintln!(
+                    "==leaks== {} more bytes allocated at other sites",
+                    bytes - printed_bytes
+                );
+            }
+        });
+    }
+
+    #[inline(never)]
+    fn track(&self, ptr: *mut u8, size: usize) {
+        if ptr.is_null() {
+            return;
+        }
+        guarded(|| {
+            // Leave out this function.
+            let frames = backtrace::capture(1);
+            self.tables.lock().record(ptr as usize, size, &frames);
+        });
+    }
+
+    fn untrack(&self, ptr: *mut u8) -> Option<Slot> {
+        self.tables.lock().remove(ptr as usize)
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for LeakDetector<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc(layout);
+        self.track(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = self.inner.alloc_zeroed(layout);
+        self.track(ptr, layout.size());
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Forget the block first, since its address can be reused as soon as it is freed.
+        self.untrack(ptr);
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let old = self.untrack(ptr);
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if new_ptr.is_null() {
+            // The block is still there: put it back.
+            if let Some(old) = old {
+                self.tables.lock().insert(old);
+            }
+        } else {
+            self.track(new_ptr, new_size);
+        }
+        new_ptr
+    }
+}
@@ -3,12 +3,20 @@ use std::os::raw::c_void;
 
 use libc::{free, malloc, realloc};
 
+use crate::allocators::glibc_allocator::{aligned_malloc, MIN_ALIGN};
+use crate::global_alloc::stats::AllocatorStats;
+
 pub struct GlibcMallocAlloc;
 
 unsafe impl Sync for GlibcMallocAlloc {}
 
+impl AllocatorStats for GlibcMallocAlloc {}
+
 unsafe impl GlobalAlloc for GlibcMallocAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.align() > MIN_ALIGN {
+            return aligned_malloc(layout);
+        }
         let raw_ptr = malloc(layout.size());
         raw_ptr.cast::<u8>()
     }
@@ -17,7 +25,17 @@ unsafe impl GlobalAlloc for GlibcMallocAlloc {
         free(ptr.cast::<c_void>())
     }
 
-    unsafe fn realloc(&self, ptr: *mut u8, _layout: Layout, new_size: usize) -> *mut u8 {
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.align() > MIN_ALIGN {
+            // `realloc` would lose the alignment: move the block instead.
+            let new_layout = Layout::from_size_align_unchecked(new_size, layout.align());
+            let new_ptr = aligned_malloc(new_layout);
+            if !new_ptr.is_null() {
+                std::ptr::copy_nonoverlapping(ptr, new_ptr, layout.size().min(new_size));
+                free(ptr.cast::<c_void>());
+            }
+            return new_ptr;
+        }
         realloc(ptr.cast::<c_void>(), new_size) as *mut u8
     }
 }
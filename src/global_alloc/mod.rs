@@ -1,3 +1,8 @@
 pub mod arena;
+pub mod heap_profiler;
+pub mod leak_detector;
 pub mod malloc;
+mod profile_writer;
 pub mod sbrk;
+mod site_table;
+pub mod stats;
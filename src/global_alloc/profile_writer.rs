@@ -0,0 +1,183 @@
+//! Allocation-free output for the heap profiler: a buffered file writer, and
+//! just enough of a protobuf encoder for pprof profiles.
+//!
+//! Messages are length-prefixed, so each one is encoded twice: once into a
+//! `Counter` to learn its length, then for real.
+use std::ffi::c_int;
+use std::fmt::{self, Display, Write};
+use std::io;
+
+/// Longest path accepted, nul byte included.
+const MAX_PATH: usize = 4096;
+const BUFFER_SIZE: usize = 8192;
+
+/// Destination of encoded bytes.
+pub(crate) trait Sink {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+/// Counts bytes instead of writing them.
+pub(crate) struct Counter(pub usize);
+
+impl Sink for Counter {
+    fn put(&mut self, bytes: &[u8]) {
+        self.0 += bytes.len();
+    }
+}
+
+/// Writes formatted text into a sink.
+struct SinkWriter<'a>(&'a mut dyn Sink);
+
+impl Write for SinkWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.put(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// A buffered file, created or truncated. The first error is kept and
+/// returned by `finish`.
+pub(crate) struct FileWriter {
+    fd: c_int,
+    buf: [u8; BUFFER_SIZE],
+    len: usize,
+    error: Option<io::Error>,
+}
+
+impl FileWriter {
+    pub fn create(path: &str) -> io::Result<Self> {
+        let mut c_path = [0u8; MAX_PATH];
+        if path.len() >= MAX_PATH || path.as_bytes().contains(&0) {
+            return Err(io::ErrorKind::InvalidInput.into());
+        }
+        c_path[..path.len()].copy_from_slice(path.as_bytes());
+        let fd = unsafe {
+            libc::open(
+                c_path.as_ptr().cast(),
+                libc::O_WRONLY | libc::O_CREAT | libc::O_TRUNC | libc::O_CLOEXEC,
+                0o644,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(FileWriter {
+            fd,
+            buf: [0; BUFFER_SIZE],
+            len: 0,
+            error: None,
+        })
+    }
+
+    fn flush(&mut self) {
+        let mut pending = &self.buf[..self.len];
+        while !pending.is_empty() && self.error.is_none() {
+            let written = unsafe { libc::write(self.fd, pending.as_ptr().cast(), pending.len()) };
+            if written < 0 {
+                let error = io::Error::last_os_error();
+                if error.kind() != io::ErrorKind::Interrupted {
+                    self.error = Some(error);
+                }
+                continue;
+            }
+            pending = &pending[written as usize..];
+        }
+        self.len = 0;
+    }
+
+    /// Flushes and closes the file.
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush();
+        if unsafe { libc::close(self.fd) } != 0 && self.error.is_none() {
+            self.error = Some(io::Error::last_os_error());
+        }
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sink for FileWriter {
+    fn put(&mut self, mut bytes: &[u8]) {
+        while !bytes.is_empty() {
+            if self.len == BUFFER_SIZE {
+                self.flush();
+            }
+            let n = bytes.len().min(BUFFER_SIZE - self.len);
+            self.buf[self.len..self.len + n].copy_from_slice(&bytes[..n]);
+            self.len += n;
+            bytes = &bytes[n..];
+        }
+    }
+}
+
+impl Write for FileWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.put(s.as_bytes());
+        Ok(())
+    }
+}
+
+const VARINT: u64 = 0;
+const LENGTH_DELIMITED: u64 = 2;
+
+fn varint(sink: &mut dyn Sink, mut value: u64) {
+    let mut bytes = [0u8; 10];
+    let mut len = 0;
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes[len] = byte;
+            len += 1;
+            break;
+        }
+        bytes[len] = byte | 0x80;
+        len += 1;
+    }
+    sink.put(&bytes[..len]);
+}
+
+fn key(sink: &mut dyn Sink, field: u64, wire_type: u64) {
+    varint(sink, (field << 3) | wire_type);
+}
+
+/// An integer field, left out when zero as protobuf does.
+pub(crate) fn uint(sink: &mut dyn Sink, field: u64, value: u64) {
+    if value != 0 {
+        key(sink, field, VARINT);
+        varint(sink, value);
+    }
+}
+
+/// A string field holding the formatted `value`.
+pub(crate) fn string(sink: &mut dyn Sink, field: u64, value: impl Display) {
+    let mut counter = Counter(0);
+    let _ = write!(SinkWriter(&mut counter), "{}", value);
+    key(sink, field, LENGTH_DELIMITED);
+    varint(sink, counter.0 as u64);
+    let _ = write!(SinkWriter(sink), "{}", value);
+}
+
+/// A packed repeated integer field.
+pub(crate) fn packed(sink: &mut dyn Sink, field: u64, values: impl Iterator<Item = u64> + Clone) {
+    let mut counter = Counter(0);
+    for value in values.clone() {
+        varint(&mut counter, value);
+    }
+    key(sink, field, LENGTH_DELIMITED);
+    varint(sink, counter.0 as u64);
+    for value in values {
+        varint(sink, value);
+    }
+}
+
+/// An embedded message field, whose fields `body` writes.
+pub(crate) fn message(sink: &mut dyn Sink, field: u64, body: impl Fn(&mut dyn Sink)) {
+    let mut counter = Counter(0);
+    body(&mut counter);
+    key(sink, field, LENGTH_DELIMITED);
+    varint(sink, counter.0 as u64);
+    body(sink);
+}
@@ -1,10 +1,13 @@
-use libc::{c_int, c_void, sbrk};
+use libc::{c_void, sbrk};
 use std::alloc::{GlobalAlloc, Layout};
 use std::ptr::null_mut;
 use std::sync::atomic::AtomicPtr;
 use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
 
-use alloc_fmt::{alloc_eprintln, alloc_println};
+use alloc_fmt::record::JsonObject;
+use alloc_fmt::{alloc_debug, alloc_trace};
+
+use crate::global_alloc::stats::AllocatorStats;
 
 pub struct SbrkAlloc {
     ptr: AtomicPtr<u8>,
@@ -12,6 +15,8 @@ pub struct SbrkAlloc {
     size: AtomicUsize,
 }
 
+const PAGE_SIZE: usize = 4096;
+
 /// Aligns the given offset to the given alignment.
 fn align_up(offset: usize, align: usize) -> usize {
     (offset + align - 1) & !(align - 1)
@@ -35,41 +40,69 @@ impl Default for SbrkAlloc {
 
 unsafe impl Sync for SbrkAlloc {}
 
+impl AllocatorStats for SbrkAlloc {
+    /// The current region of the heap: its size and how much of it is used.
+    fn write_stats(&self, stats: &mut JsonObject) {
+        stats
+            .field("heap_size", self.size.load(Relaxed))
+            .field("heap_offset", self.offset.load(Relaxed));
+    }
+}
+
 unsafe impl GlobalAlloc for SbrkAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
         let size = layout.size();
         let align = layout.align();
-        let offset = self.offset.load(Relaxed);
-        let ptr = align_up(offset, align);
-        let new_offset = ptr + size;
-        if new_offset > self.size.load(Relaxed) {
-            let ptr = self.increase_heap_size(size);
-            if ptr.is_null() {
+        loop {
+            let arena = self.ptr.load(Relaxed);
+            let arena_size = self.size.load(Relaxed);
+            // Align the address rather than the offset, the break being byte-aligned.
+            let base = arena as usize;
+            let ptr = align_up(base + self.offset.load(Relaxed), align) - base;
+            let new_offset = ptr + size;
+
+            if !arena.is_null() && new_offset <= arena_size {
+                self.offset.store(new_offset, Relaxed);
+                let ptr = arena.add(ptr);
+                alloc_trace!(
+                    "allocating {:?} bytes at {:p} (align: {:?})",
+                    size,
+                    ptr,
+                    align
+                );
+                return ptr;
+            }
+
+            // Leave room for the alignment padding, in case the new memory does
+            // not follow the current region.
+            let increment = align_up(new_offset.saturating_sub(arena_size) + align, PAGE_SIZE);
+            let new_memory = self.increase_heap_size(increment);
+            if new_memory.is_null() {
                 return null_mut();
             }
-            self.ptr.store(ptr, Relaxed);
-            self.size.fetch_add(size, Relaxed);
+            if !arena.is_null() && arena.wrapping_add(arena_size) == new_memory {
+                self.size.fetch_add(increment, Relaxed);
+            } else {
+                // Something else moved the break since the last call: start a new region.
+                self.ptr.store(new_memory, Relaxed);
+                self.size.store(increment, Relaxed);
+                self.offset.store(0, Relaxed);
+            }
         }
-        self.offset.store(new_offset, Relaxed);
-        let ptr = self.ptr.load(Relaxed).cast::<u8>().add(offset);
-        alloc_eprintln!(
-            "allocating {:?} bytes at {:p} (align: {:?})",
-            size,
-            ptr,
-            align
-        );
-        ptr
     }
 
     unsafe fn dealloc(&self, ptr: *mut u8, _layout: Layout) {
-        alloc_eprintln!("deallocating {:p}", ptr);
+        alloc_trace!("deallocating {:p}", ptr);
     }
 }
 
 impl SbrkAlloc {
     pub fn increase_heap_size(&self, size: usize) -> *mut u8 {
-        alloc_println!("increase_heap_size by {}", size);
-        let ptr = unsafe { sbrk(size as c_int) };
+        alloc_debug!("increase_heap_size by {}", size);
+        let Ok(increment) = size.try_into() else {
+            return null_mut();
+        };
+        let ptr = unsafe { sbrk(increment) };
         if ptr == -1isize as *mut c_void {
             return null_mut();
         }
@@ -0,0 +1,189 @@
+//! Allocation-free bookkeeping shared by the instrumenting wrappers: tracked
+//! blocks keyed by address, and the stacks that allocated them.
+use std::cell::Cell;
+
+use alloc_fmt::backtrace::Frames;
+
+pub(crate) const NO_SITE: u32 = u32::MAX;
+
+thread_local! {
+    /// Set while a wrapper runs its own code, so that allocations it might cause go untracked.
+    static BUSY: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Runs `f` unless a wrapper is already running on this thread.
+pub(crate) fn guarded<R>(f: impl FnOnce() -> R) -> Option<R> {
+    BUSY.try_with(|busy| {
+        if busy.replace(true) {
+            return None;
+        }
+        let result = f();
+        busy.set(false);
+        Some(result)
+    })
+    .ok()
+    .flatten()
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Slot {
+    /// Null for an empty slot.
+    pub ptr: usize,
+    pub size: usize,
+    pub site: u32,
+}
+
+impl Slot {
+    const EMPTY: Slot = Slot {
+        ptr: 0,
+        size: 0,
+        site: NO_SITE,
+    };
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Site {
+    pub frames: Frames,
+    hash: u64,
+    pub live_blocks: usize,
+    pub live_bytes: usize,
+    pub total_blocks: usize,
+    pub total_bytes: usize,
+}
+
+impl Site {
+    const EMPTY: Site = Site {
+        frames: Frames::EMPTY,
+        hash: 0,
+        live_blocks: 0,
+        live_bytes: 0,
+        total_blocks: 0,
+        total_bytes: 0,
+    };
+
+    pub fn is_used(&self) -> bool {
+        !self.frames.as_slice().is_empty()
+    }
+}
+
+/// Up to `SLOTS * 3 / 4` tracked blocks and `SITES - 1` sites, in fixed-size
+/// open-addressing tables.
+pub(crate) struct Tables<const SLOTS: usize, const SITES: usize> {
+    slots: [Slot; SLOTS],
+    pub live: usize,
+    pub sites: [Site; SITES],
+    site_count: usize,
+    /// Blocks that did not fit in `slots`.
+    pub untracked: usize,
+}
+
+fn slot_index<const SLOTS: usize>(ptr: usize) -> usize {
+    ((ptr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32) % SLOTS
+}
+
+impl<const SLOTS: usize, const SITES: usize> Tables<SLOTS, SITES> {
+    pub const fn new() -> Self {
+        Tables {
+            slots: [Slot::EMPTY; SLOTS],
+            live: 0,
+            sites: [Site::EMPTY; SITES],
+            site_count: 0,
+            untracked: 0,
+        }
+    }
+
+    pub fn live_bytes(&self) -> usize {
+        self.slots.iter().map(|slot| slot.size).sum()
+    }
+
+    /// Tracks a new block allocated from `frames`.
+    pub fn record(&mut self, ptr: usize, size: usize, frames: &Frames) {
+        let site = self.site(frames);
+        if let Some(site) = self.sites.get_mut(site as usize) {
+            site.total_blocks += 1;
+            site.total_bytes += size;
+        }
+        self.insert(Slot { ptr, size, site });
+    }
+
+    /// Returns the index of the site of `frames`, adding it if needed.
+    fn site(&mut self, frames: &Frames) -> u32 {
+        let hash = frames.hash();
+        let mut index = hash as usize % SITES;
+        loop {
+            let site = &mut self.sites[index];
+            if !site.is_used() {
+                // Keep one free entry so that lookups always terminate.
+                if self.site_count == SITES - 1 {
+                    return NO_SITE;
+                }
+                self.site_count += 1;
+                *site = Site {
+                    frames: *frames,
+                    hash,
+                    ..Site::EMPTY
+                };
+                return index as u32;
+            }
+            if site.hash == hash && site.frames.as_slice() == frames.as_slice() {
+                return index as u32;
+            }
+            index = (index + 1) % SITES;
+        }
+    }
+
+    /// Tracks a block again, after `remove`.
+    pub fn insert(&mut self, slot: Slot) {
+        // Linear probing degrades past three quarters full.
+        if self.live >= SLOTS / 4 * 3 {
+            self.untracked += 1;
+            return;
+        }
+        let mut index = slot_index::<SLOTS>(slot.ptr);
+        while self.slots[index].ptr != 0 {
+            index = (index + 1) % SLOTS;
+        }
+        self.slots[index] = slot;
+        self.live += 1;
+        if let Some(site) = self.sites.get_mut(slot.site as usize) {
+            site.live_blocks += 1;
+            site.live_bytes += slot.size;
+        }
+    }
+
+    pub fn remove(&mut self, ptr: usize) -> Option<Slot> {
+        let mut index = slot_index::<SLOTS>(ptr);
+        while self.slots[index].ptr != ptr {
+            if self.slots[index].ptr == 0 {
+                return None;
+            }
+            index = (index + 1) % SLOTS;
+        }
+        let removed = self.slots[index];
+        self.live -= 1;
+        if let Some(site) = self.sites.get_mut(removed.site as usize) {
+            site.live_blocks -= 1;
+            site.live_bytes -= removed.size;
+        }
+
+        // Shift the following entries back, so that no probe sequence is broken.
+        let mut hole = index;
+        let mut next = index;
+        loop {
+            next = (next + 1) % SLOTS;
+            let ptr = self.slots[next].ptr;
+            if ptr == 0 {
+                break;
+            }
+            let home = slot_index::<SLOTS>(ptr);
+            let distance_to_hole = (hole + SLOTS - home) % SLOTS;
+            let distance_to_next = (next + SLOTS - home) % SLOTS;
+            if distance_to_hole < distance_to_next {
+                self.slots[hole] = self.slots[next];
+                hole = next;
+            }
+        }
+        self.slots[hole] = Slot::EMPTY;
+        Some(removed)
+    }
+}
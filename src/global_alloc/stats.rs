@@ -0,0 +1,294 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ffi::c_int;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicUsize, Ordering::Relaxed};
+
+use alloc_fmt::record::{BufferedFdWriter, JsonObject};
+
+/// Size classes are powers of two from 8 bytes up to 1 MiB, then everything larger.
+const SIZE_CLASSES: usize = 19;
+const SMALLEST_CLASS_BITS: u32 = 3;
+const DUMP_BUFFER_SIZE: usize = 1024;
+
+/// Number of `SIGUSR1` received. Each allocator remembers the count it last
+/// dumped at, so that every instance dumps once per signal.
+static DUMPS_REQUESTED: AtomicUsize = AtomicUsize::new(0);
+static HANDLER_INSTALLED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn request_dump(_signal: c_int) {
+    DUMPS_REQUESTED.fetch_add(1, Relaxed);
+}
+
+/// Figures specific to an allocator, added to the stats dumped by `StatsAlloc`.
+pub trait AllocatorStats {
+    /// Adds fields to the `allocator` object of a dump. Called from allocator
+    /// code: it must not allocate.
+    fn write_stats(&self, _stats: &mut JsonObject) {}
+}
+
+impl AllocatorStats for System {}
+
+fn size_class(size: usize) -> usize {
+    let bits = usize::BITS - size.saturating_sub(1).leading_zeros();
+    (bits.saturating_sub(SMALLEST_CLASS_BITS) as usize).min(SIZE_CLASSES - 1)
+}
+
+/// Largest size in the class `index`, `None` for the last one.
+fn size_class_max(index: usize) -> Option<usize> {
+    (index < SIZE_CLASSES - 1).then(|| 1 << (index as u32 + SMALLEST_CLASS_BITS))
+}
+
+/// A `GlobalAlloc` wrapper counting live blocks and bytes, overall and per
+/// power-of-two size class, and dumping them as JSON on demand.
+///
+/// The totals count the blocks allocated since the start and their sizes. A
+/// `realloc` moves a block to its new size and class, but is not counted as a
+/// new block.
+///
+/// Call `dump` at any time, or `dump_on_signal` to have the process dump them
+/// when it receives `SIGUSR1`. The signal handler only sets a flag: the dump is
+/// written by the next allocation or deallocation, from the thread making it,
+/// so an idle process does not dump until it allocates again. Every instance
+/// with `dump_on_signal` enabled dumps once per signal.
+pub struct StatsAlloc<A: GlobalAlloc> {
+    inner: A,
+    live_blocks: AtomicUsize,
+    live_bytes: AtomicUsize,
+    total_blocks: AtomicUsize,
+    total_bytes: AtomicUsize,
+    size_classes: [AtomicUsize; SIZE_CLASSES],
+    /// Where to dump on `SIGUSR1`, -1 if not enabled.
+    signal_fd: AtomicI32,
+    /// Value of `DUMPS_REQUESTED` at the last dump on `SIGUSR1`.
+    dumps_done: AtomicUsize,
+}
+
+impl<A: GlobalAlloc> StatsAlloc<A> {
+    pub const fn new(inner: A) -> Self {
+        StatsAlloc {
+            inner,
+            live_blocks: AtomicUsize::new(0),
+            live_bytes: AtomicUsize::new(0),
+            total_blocks: AtomicUsize::new(0),
+            total_bytes: AtomicUsize::new(0),
+            size_classes: [const { AtomicUsize::new(0) }; SIZE_CLASSES],
+            signal_fd: AtomicI32::new(-1),
+            dumps_done: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of blocks and bytes currently allocated.
+    pub fn live(&self) -> (usize, usize) {
+        (
+            self.live_blocks.load(Relaxed),
+            self.live_bytes.load(Relaxed),
+        )
+    }
+
+    fn track(&self, size: usize) {
+        self.track_live(size);
+        self.total_blocks.fetch_add(1, Relaxed);
+        self.total_bytes.fetch_add(size, Relaxed);
+    }
+
+    fn track_live(&self, size: usize) {
+        self.live_blocks.fetch_add(1, Relaxed);
+        self.live_bytes.fetch_add(size, Relaxed);
+        self.size_classes[size_class(size)].fetch_add(1, Relaxed);
+    }
+
+    fn untrack(&self, size: usize) {
+        self.live_blocks.fetch_sub(1, Relaxed);
+        self.live_bytes.fetch_sub(size, Relaxed);
+        self.size_classes[size_class(size)].fetch_sub(1, Relaxed);
+    }
+}
+
+impl<A: GlobalAlloc + AllocatorStats> StatsAlloc<A> {
+    /// Writes the current stats to the file descriptor `fd`, as one line of JSON.
+    ///
+    /// Size classes are listed by the largest size they hold, `null` for the
+    /// last one; empty classes are left out.
+    pub fn dump(&self, fd: c_int) {
+        let mut out = BufferedFdWriter::<DUMP_BUFFER_SIZE>::new(fd);
+        let mut stats = JsonObject::new(&mut out);
+        stats
+            .field("live_blocks", self.live_blocks.load(Relaxed))
+            .field("live_bytes", self.live_bytes.load(Relaxed))
+            .field("total_blocks", self.total_blocks.load(Relaxed))
+            .field("total_bytes", self.total_bytes.load(Relaxed))
+            .array("size_classes", |classes| {
+                for (index, blocks) in self.size_classes.iter().enumerate() {
+                    let blocks = blocks.load(Relaxed);
+                    if blocks > 0 {
+                        classes.object(|class| {
+                            class
+                                .field("max_size", size_class_max(index))
+                                .field("live_blocks", blocks);
+                        });
+                    }
+                }
+            })
+            .object("allocator", |allocator| self.inner.write_stats(allocator));
+        // `BufferedFdWriter` never fails.
+        let _ = stats.finish();
+        let _ = out.write_char('\n');
+    }
+
+    /// Dumps the stats to the file descriptor `fd` whenever the process
+    /// receives `SIGUSR1`, installing a handler for it the first time.
+    pub fn dump_on_signal(&self, fd: c_int) {
+        // Signals received before are not for this allocator.
+        self.dumps_done
+            .store(DUMPS_REQUESTED.load(Relaxed), Relaxed);
+        self.signal_fd.store(fd, Relaxed);
+        if !HANDLER_INSTALLED.swap(true, Relaxed) {
+            unsafe {
+                let mut action: libc::sigaction = std::mem::zeroed();
+                action.sa_sigaction = request_dump as extern "C" fn(c_int) as libc::sighandler_t;
+                action.sa_flags = libc::SA_RESTART;
+                libc::sigemptyset(&mut action.sa_mask);
+                libc::sigaction(libc::SIGUSR1, &action, std::ptr::null_mut());
+            }
+        }
+    }
+
+    /// Dumps the stats if `SIGUSR1` was received since the last dump.
+    fn dump_if_requested(&self) {
+        let fd = self.signal_fd.load(Relaxed);
+        if fd < 0 {
+            return;
+        }
+        let requested = DUMPS_REQUESTED.load(Relaxed);
+        let done = self.dumps_done.load(Relaxed);
+        if requested != done
+            && self
+                .dumps_done
+                .compare_exchange(done, requested, Relaxed, Relaxed)
+                .is_ok()
+        {
+            self.dump(fd);
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc + AllocatorStats> GlobalAlloc for StatsAlloc<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.dump_if_requested();
+        let ptr = self.inner.alloc(layout);
+        if !ptr.is_null() {
+            self.track(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        self.dump_if_requested();
+        let ptr = self.inner.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            self.track(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.dump_if_requested();
+        self.untrack(layout.size());
+        self.inner.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.dump_if_requested();
+        let new_ptr = self.inner.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            self.untrack(layout.size());
+            self.track_live(new_size);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the read and write ends of a new pipe.
+    fn pipe() -> (c_int, c_int) {
+        let mut fds = [0; 2];
+        assert_eq!(unsafe { libc::pipe(fds.as_mut_ptr()) }, 0);
+        (fds[0], fds[1])
+    }
+
+    fn read_line(fd: c_int) -> String {
+        let mut buf = [0u8; 1024];
+        let len = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        assert!(len > 0);
+        String::from_utf8(buf[..len as usize].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn dump() {
+        let allocator = StatsAlloc::new(System);
+        let (read_fd, write_fd) = pipe();
+        unsafe {
+            let small = allocator.alloc(Layout::from_size_align(16, 8).unwrap());
+            let mib = allocator.alloc(Layout::from_size_align(1 << 20, 8).unwrap());
+            let large = allocator.alloc(Layout::from_size_align(100, 8).unwrap());
+            // Moves the block to the last class, without counting a new one.
+            let large = allocator.realloc(large, Layout::from_size_align(100, 8).unwrap(), 2 << 20);
+            allocator.dump(write_fd);
+            assert_eq!(
+                read_line(read_fd),
+                concat!(
+                    r#"{"live_blocks":3,"live_bytes":3145744,"total_blocks":3,"total_bytes":1048692,"#,
+                    r#""size_classes":[{"max_size":16,"live_blocks":1},"#,
+                    r#"{"max_size":1048576,"live_blocks":1},{"max_size":null,"live_blocks":1}],"#,
+                    r#""allocator":{}}"#,
+                    "\n"
+                )
+            );
+            allocator.dealloc(small, Layout::from_size_align(16, 8).unwrap());
+            allocator.dealloc(mib, Layout::from_size_align(1 << 20, 8).unwrap());
+            allocator.dealloc(large, Layout::from_size_align(2 << 20, 8).unwrap());
+            libc::close(read_fd);
+            libc::close(write_fd);
+        }
+        assert_eq!(allocator.live(), (0, 0));
+    }
+
+    #[test]
+    fn dump_on_signal() {
+        let first = StatsAlloc::new(System);
+        let second = StatsAlloc::new(System);
+        let (first_read, first_write) = pipe();
+        let (second_read, second_write) = pipe();
+        first.dump_on_signal(first_write);
+        second.dump_on_signal(second_write);
+        let layout = Layout::new::<u64>();
+        unsafe {
+            let ptr = first.alloc(layout);
+            assert_eq!(libc::raise(libc::SIGUSR1), 0);
+            // The next call dumps the stats from before it.
+            first.dealloc(ptr, layout);
+            let line = read_line(first_read);
+            assert!(
+                line.starts_with(r#"{"live_blocks":1,"live_bytes":8,"total_blocks":1,"#),
+                "{}",
+                line
+            );
+            assert!(line.ends_with("}\n"), "{}", line);
+            // The signal is not used up by the first allocator.
+            let ptr = second.alloc(layout);
+            let line = read_line(second_read);
+            assert!(
+                line.starts_with(r#"{"live_blocks":0,"live_bytes":0,"total_blocks":0,"#),
+                "{}",
+                line
+            );
+            second.dealloc(ptr, layout);
+            for fd in [first_read, first_write, second_read, second_write] {
+                libc::close(fd);
+            }
+        }
+    }
+}
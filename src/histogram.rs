@@ -0,0 +1,139 @@
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+
+/// Number of bits used for the linear sub-buckets of each power of two.
+/// 5 bits gives 32 sub-buckets, i.e. a relative error below ~3%.
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+/// Values below `SUB_BUCKETS` are recorded exactly, every other power of two
+/// up to `u64::MAX` gets `SUB_BUCKETS` linear buckets.
+const BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+/// A log-linear (HDR-style) histogram of `u64` values.
+///
+/// All the buckets are allocated when the histogram is created, so recording a
+/// value never allocates. Recording is lock-free and can be shared between threads,
+/// but every value touches five shared counters: threads recording at a high rate
+/// should each fill their own histogram and `merge` them afterwards.
+pub struct Histogram {
+    counts: Box<[AtomicU64]>,
+    total: AtomicU64,
+    sum: AtomicU64,
+    min: AtomicU64,
+    max: AtomicU64,
+}
+
+/// Returns the index of the bucket holding `value`.
+fn bucket_index(value: u64) -> usize {
+    if value < SUB_BUCKETS as u64 {
+        return value as usize;
+    }
+    let msb = 63 - value.leading_zeros();
+    let shift = msb - SUB_BUCKET_BITS;
+    let sub_bucket = (value >> shift) as usize - SUB_BUCKETS;
+    (shift as usize + 1) * SUB_BUCKETS + sub_bucket
+}
+
+/// Returns the highest value that lands in the bucket `index`.
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let shift = (index / SUB_BUCKETS - 1) as u32;
+    let sub_bucket = (index % SUB_BUCKETS) as u64;
+    let lower = (SUB_BUCKETS as u64 + sub_bucket) << shift;
+    lower + ((1u64 << shift) - 1)
+}
+
+impl Histogram {
+    pub fn new() -> Self {
+        Histogram {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            total: AtomicU64::new(0),
+            sum: AtomicU64::new(0),
+            min: AtomicU64::new(u64::MAX),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    #[inline]
+    pub fn record(&self, value: u64) {
+        self.counts[bucket_index(value)].fetch_add(1, Relaxed);
+        self.total.fetch_add(1, Relaxed);
+        self.sum.fetch_add(value, Relaxed);
+        self.min.fetch_min(value, Relaxed);
+        self.max.fetch_max(value, Relaxed);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.total.load(Relaxed)
+    }
+
+    pub fn min(&self) -> u64 {
+        if self.count() == 0 {
+            0
+        } else {
+            self.min.load(Relaxed)
+        }
+    }
+
+    pub fn max(&self) -> u64 {
+        self.max.load(Relaxed)
+    }
+
+    pub fn mean(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => self.sum.load(Relaxed) as f64 / count as f64,
+        }
+    }
+
+    /// Returns the value below which `percentile` percent of the recorded values fall.
+    ///
+    /// The result is the upper bound of the matching bucket, capped by the maximum
+    /// recorded value.
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let total = self.count();
+        if total == 0 {
+            return 0;
+        }
+        let rank = ((percentile / 100.0) * total as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.counts.iter().enumerate() {
+            seen += count.load(Relaxed);
+            if seen >= rank {
+                return bucket_upper_bound(index).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// Adds the values recorded by `other` to this histogram.
+    pub fn merge(&self, other: &Histogram) {
+        for (count, other) in self.counts.iter().zip(other.counts.iter()) {
+            let other = other.load(Relaxed);
+            if other > 0 {
+                count.fetch_add(other, Relaxed);
+            }
+        }
+        self.total.fetch_add(other.total.load(Relaxed), Relaxed);
+        self.sum.fetch_add(other.sum.load(Relaxed), Relaxed);
+        self.min.fetch_min(other.min.load(Relaxed), Relaxed);
+        self.max.fetch_max(other.max.load(Relaxed), Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Relaxed);
+        }
+        self.total.store(0, Relaxed);
+        self.sum.store(0, Relaxed);
+        self.min.store(u64::MAX, Relaxed);
+        self.max.store(0, Relaxed);
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
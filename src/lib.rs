@@ -1,15 +1,32 @@
 #![feature(allocator_api)]
 
 mod allocators;
+pub mod conformance;
+pub mod fuzz;
 mod global_alloc;
+mod histogram;
 
 pub use allocators::arena_allocator::ArenaAllocator;
+pub use allocators::checked_allocator::CheckedAllocator;
+pub use allocators::combinators::{Bucketizer, Fallback, Owns, Segregator};
+pub use allocators::downward_arena_allocator::DownwardArenaAllocator;
 pub use allocators::glibc_allocator::GlibcMallocAllocator;
+pub use allocators::guard_page_allocator::{GuardMode, GuardPageAllocator};
 pub use allocators::jemalloc_allocator::JemallocAllocator;
+pub use allocators::latency_allocator::LatencyAllocator;
 pub use allocators::mimalloc_allocator::MiMallocAllocator;
 pub use allocators::sbrk_allocator::SbrkAllocator;
+pub use allocators::thread_cache_allocator::ThreadCache;
+pub use allocators::thread_safe_allocator::{
+    PerThreadAllocator, ShardedAllocator, SharedAllocator, SpinLockAllocator, Unshared,
+};
 pub use allocators::verbose_allocator::VerboseAllocator;
 
 pub use global_alloc::arena::SimpleAlloc;
+pub use global_alloc::heap_profiler::{HeapProfiler, ProfileFormat};
+pub use global_alloc::leak_detector::LeakDetector;
 pub use global_alloc::malloc::GlibcMallocAlloc;
 pub use global_alloc::sbrk::SbrkAlloc;
+pub use global_alloc::stats::{AllocatorStats, StatsAlloc};
+
+pub use histogram::Histogram;
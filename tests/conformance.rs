@@ -0,0 +1,278 @@
+#![feature(allocator_api)]
+use bumpalo::Bump;
+use memory_allocator_performance_rs::{
+    conformance, ArenaAllocator, Bucketizer, CheckedAllocator, DownwardArenaAllocator, Fallback,
+    GlibcMallocAlloc, GlibcMallocAllocator, GuardMode, GuardPageAllocator, HeapProfiler,
+    JemallocAllocator, LatencyAllocator, LeakDetector, MiMallocAllocator, Owns, PerThreadAllocator,
+    Segregator, ShardedAllocator, SharedAllocator, SimpleAlloc, SpinLockAllocator, StatsAlloc,
+    ThreadCache, Unshared, VerboseAllocator,
+};
+use std::alloc::{Allocator, Layout, System};
+
+const ARENA_CAPACITY: usize = 64 * 1024 * 1024;
+const STATIC_ARENA_CAPACITY: usize = 1024 * 1024;
+const FALLBACK_ARENA_CAPACITY: usize = 64 * 1024;
+
+#[test]
+fn system() {
+    conformance::check(|| System);
+}
+
+#[test]
+fn glibc_malloc() {
+    conformance::check(|| GlibcMallocAllocator);
+    conformance::check_clones(|| GlibcMallocAllocator);
+}
+
+#[test]
+fn jemalloc() {
+    conformance::check(JemallocAllocator::default);
+    conformance::check_clones(JemallocAllocator::default);
+}
+
+#[test]
+fn mimalloc() {
+    conformance::check(|| MiMallocAllocator);
+    conformance::check_clones(|| MiMallocAllocator);
+}
+
+#[test]
+fn arena() {
+    conformance::check(|| ArenaAllocator::with_capacity(ARENA_CAPACITY));
+    conformance::check_clones(|| ArenaAllocator::with_capacity(ARENA_CAPACITY));
+}
+
+#[test]
+fn arena_from_ptr() {
+    let make = || {
+        let memory = Box::leak(vec![0u8; STATIC_ARENA_CAPACITY].into_boxed_slice());
+        ArenaAllocator::from_ptr(memory)
+    };
+    conformance::check_clones(make);
+}
+
+#[test]
+fn downward_arena() {
+    conformance::check(|| DownwardArenaAllocator::with_capacity(ARENA_CAPACITY));
+    conformance::check_clones(|| DownwardArenaAllocator::with_capacity(ARENA_CAPACITY));
+}
+
+#[test]
+fn downward_arena_from_ptr() {
+    let make = || {
+        let memory = Box::leak(vec![0u8; STATIC_ARENA_CAPACITY].into_boxed_slice());
+        DownwardArenaAllocator::from_ptr(memory)
+    };
+    conformance::check_clones(make);
+}
+
+#[test]
+fn fallback() {
+    // Small enough for the secondary allocator to be used too.
+    let make = || {
+        Fallback::new(
+            ArenaAllocator::with_capacity(FALLBACK_ARENA_CAPACITY),
+            GlibcMallocAllocator,
+        )
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+
+    let make = || {
+        Fallback::new(
+            DownwardArenaAllocator::with_capacity(FALLBACK_ARENA_CAPACITY),
+            MiMallocAllocator,
+        )
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+}
+
+#[test]
+fn owns() {
+    let layout = Layout::new::<u64>();
+    let arena = ArenaAllocator::with_capacity(STATIC_ARENA_CAPACITY);
+    let downward = DownwardArenaAllocator::with_capacity(STATIC_ARENA_CAPACITY);
+    let blocks = [
+        arena.allocate(layout).unwrap().cast(),
+        downward.allocate(layout).unwrap().cast(),
+        MiMallocAllocator.allocate(layout).unwrap().cast(),
+    ];
+    let owners: [&dyn Owns; 3] = [&arena, &downward, &MiMallocAllocator];
+    for (i, owner) in owners.iter().enumerate() {
+        for (j, &block) in blocks.iter().enumerate() {
+            assert_eq!(owner.owns(block), i == j, "allocator {}, block {}", i, j);
+        }
+    }
+    unsafe { MiMallocAllocator.deallocate(blocks[2], layout) };
+}
+
+#[test]
+fn segregator() {
+    // Size classes for small blocks, one mapping per large block.
+    let make = || {
+        Segregator::<4096, _, _>::new(
+            ThreadCache::new(GlibcMallocAllocator),
+            GuardPageAllocator::new(GuardMode::Overflow).unmap_freed(),
+        )
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+
+    // 100 bytes is not a size class of ThreadCache, which returns 128-byte
+    // blocks: freeing them with their length must not reach glibc.
+    let make =
+        || Segregator::<100, _, _>::new(ThreadCache::new(MiMallocAllocator), GlibcMallocAllocator);
+    conformance::check(make);
+    conformance::check_clones(make);
+
+    let make = || {
+        Segregator::<1024, _, _>::new(
+            ArenaAllocator::with_capacity(ARENA_CAPACITY),
+            GlibcMallocAllocator,
+        )
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+}
+
+#[test]
+fn bucketizer() {
+    let make = || {
+        Segregator::<4095, _, _>::new(
+            Bucketizer::<_, 0, 512, 8>::new(|| MiMallocAllocator),
+            GlibcMallocAllocator,
+        )
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+}
+
+#[test]
+fn checked() {
+    conformance::check(|| CheckedAllocator::new(GlibcMallocAllocator));
+}
+
+#[test]
+#[should_panic(expected = "double free")]
+fn checked_double_free() {
+    alloc_fmt::set_catchable_panics(true);
+    let allocator = CheckedAllocator::new(GlibcMallocAllocator);
+    let layout = Layout::new::<u64>();
+    let ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+    unsafe {
+        allocator.deallocate(ptr, layout);
+        allocator.deallocate(ptr, layout);
+    }
+}
+
+#[test]
+#[should_panic(expected = "buffer overflow")]
+fn checked_overflow() {
+    alloc_fmt::set_catchable_panics(true);
+    let allocator = CheckedAllocator::new(GlibcMallocAllocator);
+    let layout = Layout::new::<u64>();
+    let ptr = allocator.allocate(layout).unwrap().cast::<u8>();
+    unsafe {
+        ptr.add(layout.size()).write(0);
+        allocator.deallocate(ptr, layout);
+    }
+}
+
+#[test]
+fn checked_global() {
+    static ALLOCATOR: CheckedAllocator<GlibcMallocAllocator> =
+        CheckedAllocator::new(GlibcMallocAllocator);
+    conformance::check_global(&ALLOCATOR);
+}
+
+#[test]
+fn guard_page() {
+    conformance::check(|| GuardPageAllocator::new(GuardMode::Overflow));
+    conformance::check(|| GuardPageAllocator::new(GuardMode::Underflow).unmap_freed());
+    conformance::check_clones(|| GuardPageAllocator::new(GuardMode::Overflow));
+    conformance::check_global(&GuardPageAllocator::new(GuardMode::Underflow));
+}
+
+#[test]
+fn latency() {
+    conformance::check(|| LatencyAllocator::new(GlibcMallocAllocator));
+    conformance::check_clones(|| LatencyAllocator::new(GlibcMallocAllocator));
+}
+
+#[test]
+fn thread_cache() {
+    conformance::check(|| ThreadCache::new(GlibcMallocAllocator));
+    conformance::check_clones(|| ThreadCache::new(GlibcMallocAllocator));
+}
+
+#[test]
+fn shared() {
+    conformance::check(|| SharedAllocator::new(GlibcMallocAllocator));
+    conformance::check_clones(|| SharedAllocator::new(GlibcMallocAllocator));
+}
+
+#[test]
+fn spin_lock() {
+    conformance::check(|| SpinLockAllocator::new(GlibcMallocAllocator));
+    conformance::check_clones(|| SpinLockAllocator::new(GlibcMallocAllocator));
+
+    let make = || {
+        SpinLockAllocator::new(unsafe {
+            Unshared::new(ArenaAllocator::with_capacity(ARENA_CAPACITY))
+        })
+    };
+    conformance::check(make);
+    conformance::check_clones(make);
+    conformance::check(|| SpinLockAllocator::new(unsafe { Unshared::new(Bump::new()) }));
+}
+
+#[test]
+fn sharded() {
+    conformance::check(|| ShardedAllocator::new(4, || GlibcMallocAllocator));
+    conformance::check_clones(|| ShardedAllocator::new(4, || GlibcMallocAllocator));
+}
+
+#[test]
+fn per_thread() {
+    conformance::check(|| PerThreadAllocator::new(|| GlibcMallocAllocator));
+    conformance::check_clones(|| PerThreadAllocator::new(|| GlibcMallocAllocator));
+}
+
+#[test]
+fn verbose() {
+    conformance::check(|| VerboseAllocator::new(GlibcMallocAllocator));
+}
+
+#[test]
+fn simple_alloc() {
+    static ALLOCATOR: SimpleAlloc = SimpleAlloc::new();
+    conformance::check_global(&ALLOCATOR);
+}
+
+#[test]
+fn glibc_malloc_alloc() {
+    conformance::check_global(&GlibcMallocAlloc);
+}
+
+#[test]
+fn heap_profiler() {
+    static EVERY_ALLOCATION: HeapProfiler<System> = HeapProfiler::with_sample_interval(System, 1);
+    static SAMPLED: HeapProfiler<System> = HeapProfiler::new(System);
+    conformance::check_global(&EVERY_ALLOCATION);
+    conformance::check_global(&SAMPLED);
+}
+
+#[test]
+fn leak_detector() {
+    static ALLOCATOR: LeakDetector<System> = LeakDetector::new(System);
+    conformance::check_global(&ALLOCATOR);
+    assert_eq!(ALLOCATOR.live(), (0, 0));
+}
+
+#[test]
+fn stats() {
+    static ALLOCATOR: StatsAlloc<System> = StatsAlloc::new(System);
+    conformance::check_global(&ALLOCATOR);
+    assert_eq!(ALLOCATOR.live(), (0, 0));
+}
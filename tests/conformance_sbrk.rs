@@ -0,0 +1,74 @@
+//! The sbrk-based allocators move the program break, which is not thread-safe,
+//! so they get a test binary of their own with a single test.
+#![feature(allocator_api)]
+use memory_allocator_performance_rs::{
+    conformance, Fallback, GlibcMallocAllocator, MiMallocAllocator, Owns, SbrkAlloc, SbrkAllocator,
+};
+use std::{
+    alloc::{Allocator, Layout},
+    ptr::NonNull,
+};
+
+#[test]
+fn sbrk() {
+    conformance::check(SbrkAllocator::new);
+    conformance::check_clones(SbrkAllocator::new);
+
+    let allocator = SbrkAllocator::new();
+    let layout = Layout::new::<u64>();
+    let ptr = allocator.allocate(layout).unwrap().cast();
+    let foreign = MiMallocAllocator.allocate(layout).unwrap().cast();
+    assert!(allocator.owns(ptr));
+    assert!(!allocator.owns(foreign));
+    unsafe { MiMallocAllocator.deallocate(foreign, layout) };
+
+    let make = || Fallback::new(SbrkAllocator::new(), MiMallocAllocator);
+    conformance::check(make);
+    conformance::check_clones(make);
+
+    // Memory that other code gets from sbrk between two regions, as glibc does
+    // for its main arena, is not claimed.
+    let allocator = SbrkAllocator::new();
+    let first = allocator.allocate(layout).unwrap().cast();
+    let foreign = unsafe { libc::sbrk(4096) }.cast::<u8>();
+    let second = allocator
+        .allocate(Layout::array::<u8>(8192).unwrap())
+        .unwrap()
+        .cast::<u8>();
+    assert!(second.as_ptr() > foreign);
+    assert!(allocator.owns(first));
+    assert!(allocator.owns(second));
+    assert!(!allocator.owns(NonNull::new(foreign).unwrap()));
+    assert!(!allocator.owns(NonNull::new(foreign.wrapping_add(2048)).unwrap()));
+
+    let glibc_layout = Layout::from_size_align(64 * 1024, 8).unwrap();
+    let glibc: Vec<_> = (0..64)
+        .map(|_| {
+            GlibcMallocAllocator
+                .allocate(glibc_layout)
+                .unwrap()
+                .cast::<u8>()
+        })
+        .collect();
+    assert!(glibc.iter().all(|&ptr| !allocator.owns(ptr)));
+    for ptr in glibc {
+        unsafe { GlibcMallocAllocator.deallocate(ptr, glibc_layout) };
+    }
+
+    // Dropping an allocator moves the break back down over its regions.
+    let allocator = SbrkAllocator::new();
+    let start = unsafe { libc::sbrk(0) };
+    allocator
+        .allocate(Layout::array::<u8>(8192).unwrap())
+        .unwrap();
+    assert_ne!(unsafe { libc::sbrk(0) }, start);
+    drop(allocator);
+    assert_eq!(unsafe { libc::sbrk(0) }, start);
+
+    let make = || Fallback::new(SbrkAllocator::new(), GlibcMallocAllocator);
+    conformance::check(make);
+    conformance::check_clones(make);
+
+    static ALLOCATOR: SbrkAlloc = SbrkAlloc::new();
+    conformance::check_global(&ALLOCATOR);
+}
@@ -0,0 +1,271 @@
+#![feature(allocator_api)]
+use memory_allocator_performance_rs::fuzz::Op;
+use memory_allocator_performance_rs::{
+    fuzz, ArenaAllocator, Bucketizer, CheckedAllocator, DownwardArenaAllocator, Fallback,
+    GlibcMallocAllocator, GuardMode, GuardPageAllocator, JemallocAllocator, LatencyAllocator,
+    MiMallocAllocator, PerThreadAllocator, Segregator, ShardedAllocator, SharedAllocator,
+    SpinLockAllocator, ThreadCache,
+};
+use std::alloc::{AllocError, Allocator, Layout, System};
+use std::ptr::NonNull;
+
+const SEEDS: std::ops::Range<u64> = 0..8;
+const OPERATIONS: usize = 2000;
+/// Small enough for the arenas to run out, which must only skip operations.
+const ARENA_CAPACITY: usize = 16 * 1024 * 1024;
+const FALLBACK_ARENA_CAPACITY: usize = 64 * 1024;
+
+#[test]
+fn glibc_malloc() {
+    fuzz::check(|| GlibcMallocAllocator, SEEDS, OPERATIONS);
+}
+
+#[test]
+fn jemalloc() {
+    fuzz::check(JemallocAllocator::default, SEEDS, OPERATIONS);
+}
+
+#[test]
+fn mimalloc() {
+    fuzz::check(|| MiMallocAllocator, SEEDS, OPERATIONS);
+}
+
+#[test]
+fn arena() {
+    fuzz::check(
+        || ArenaAllocator::with_capacity(ARENA_CAPACITY),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn downward_arena() {
+    fuzz::check(
+        || DownwardArenaAllocator::with_capacity(ARENA_CAPACITY),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn checked() {
+    fuzz::check(
+        || CheckedAllocator::new(GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn guard_page() {
+    fuzz::check(
+        || GuardPageAllocator::new(GuardMode::Overflow),
+        SEEDS,
+        OPERATIONS,
+    );
+    fuzz::check(
+        || GuardPageAllocator::new(GuardMode::Underflow).unmap_freed(),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn latency() {
+    fuzz::check(
+        || LatencyAllocator::new(GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn thread_cache() {
+    fuzz::check(|| ThreadCache::new(GlibcMallocAllocator), SEEDS, OPERATIONS);
+}
+
+#[test]
+fn shared() {
+    fuzz::check(
+        || SharedAllocator::new(GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn spin_lock() {
+    fuzz::check(
+        || SpinLockAllocator::new(GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn sharded() {
+    fuzz::check(
+        || ShardedAllocator::new(4, || GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn per_thread() {
+    fuzz::check(
+        || PerThreadAllocator::new(|| GlibcMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn fallback() {
+    // Small enough for blocks to move to the secondary allocator.
+    fuzz::check(
+        || {
+            Fallback::new(
+                ArenaAllocator::with_capacity(FALLBACK_ARENA_CAPACITY),
+                GlibcMallocAllocator,
+            )
+        },
+        SEEDS,
+        OPERATIONS,
+    );
+    fuzz::check(
+        || {
+            Fallback::new(
+                DownwardArenaAllocator::with_capacity(FALLBACK_ARENA_CAPACITY),
+                MiMallocAllocator,
+            )
+        },
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn segregator() {
+    fuzz::check(
+        || {
+            Segregator::<4096, _, _>::new(
+                ThreadCache::new(GlibcMallocAllocator),
+                GuardPageAllocator::new(GuardMode::Overflow).unmap_freed(),
+            )
+        },
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+#[test]
+fn bucketizer() {
+    // Sizes without a bucket are refused.
+    fuzz::check(
+        || Bucketizer::<_, 1, 512, 8>::new(|| MiMallocAllocator),
+        SEEDS,
+        OPERATIONS,
+    );
+    fuzz::check(
+        || {
+            Segregator::<4095, _, _>::new(
+                Bucketizer::<_, 0, 512, 8>::new(|| MiMallocAllocator),
+                GlibcMallocAllocator,
+            )
+        },
+        SEEDS,
+        OPERATIONS,
+    );
+}
+
+/// Ignores alignments above 8, the way `malloc` wrappers commonly get wrong.
+/// Blocks are placed 8 bytes into a 16-aligned allocation, so that they are
+/// never better aligned by chance.
+struct IgnoresAlignment;
+
+impl IgnoresAlignment {
+    fn inner_layout(layout: Layout) -> Layout {
+        Layout::from_size_align(layout.size() + 8, 16).unwrap()
+    }
+}
+
+unsafe impl Allocator for IgnoresAlignment {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        let ptr = System.allocate(Self::inner_layout(layout))?.cast::<u8>();
+        let ptr = unsafe { ptr.add(8) };
+        Ok(NonNull::slice_from_raw_parts(ptr, layout.size()))
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        System.deallocate(ptr.sub(8), Self::inner_layout(layout))
+    }
+}
+
+/// Clobbers the block when it fails to grow it, which it always does.
+struct ClobbersOnFailedGrow;
+
+unsafe impl Allocator for ClobbersOnFailedGrow {
+    fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+        System.allocate(layout)
+    }
+
+    unsafe fn deallocate(&self, ptr: NonNull<u8>, layout: Layout) {
+        System.deallocate(ptr, layout)
+    }
+
+    unsafe fn grow(
+        &self,
+        ptr: NonNull<u8>,
+        old_layout: Layout,
+        _new_layout: Layout,
+    ) -> Result<NonNull<[u8]>, AllocError> {
+        std::ptr::write_bytes(ptr.as_ptr(), 0xAA, old_layout.size());
+        Err(AllocError)
+    }
+}
+
+#[test]
+#[should_panic(expected = "after a failed resize")]
+fn finds_corruption_by_failed_resize() {
+    fuzz::check(|| ClobbersOnFailedGrow, SEEDS, OPERATIONS);
+}
+
+#[test]
+fn skips_failed_operations() {
+    let ops = fuzz::generate(0, OPERATIONS);
+    assert!(fuzz::run(&ArenaAllocator::with_capacity(4096), &ops).is_ok());
+}
+
+#[test]
+#[should_panic(expected = "misaligned")]
+fn finds_misalignment() {
+    fuzz::check(|| IgnoresAlignment, SEEDS, OPERATIONS);
+}
+
+#[test]
+fn minimizes_failing_sequence() {
+    let ops = fuzz::generate(0, OPERATIONS);
+    assert!(fuzz::run(&IgnoresAlignment, &ops).is_err());
+    let shortest = fuzz::minimize(|| IgnoresAlignment, ops);
+    // A single over-aligned allocation is enough to fail.
+    assert_eq!(shortest.len(), 1);
+}
+
+#[test]
+fn decodes_index_and_size_separately() {
+    // Kind, index, size (little-endian), alignment; the trailing byte is ignored.
+    let data = [2, 7, 0x34, 0x12, 3, 3, 7, 0x34, 0x12, 3, 9];
+    assert_eq!(
+        fuzz::decode(&data),
+        [
+            Op::Deallocate { index: 7 },
+            Op::Grow {
+                index: 7,
+                by: 0x1234,
+                align: 8
+            },
+        ]
+    );
+}